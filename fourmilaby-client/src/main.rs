@@ -6,14 +6,20 @@ use std::{
 
 use fourmilaby_core::{
     client::{ClientGameView, ClientInstance},
-    maze::Maze,
-    message::types::{JoinMessageBody, Message, MoveDirection, MoveMessageBody},
+    message::{
+        transmit::WireEncoding,
+        types::{JoinMessageBody, Message, MoveDirection, MoveMessageBody, PheromonEncoding},
+    },
+    protocols::encoded::EncodedChannel,
 };
 use raylib::{self, ffi::KeyboardKey, prelude::*};
 
 const TILE_WIDTH: u32 = 32;
 const WALL_WIDTH: u32 = 8;
 
+/// Colors of the other ants, by team.
+const TEAM_COLORS: [Color; 4] = [Color::ORANGE, Color::PURPLE, Color::BROWN, Color::GRAY];
+
 fn main() -> Result<(), Box<dyn Error>> {
     let arg: Option<String> = std::env::args().skip(1).next();
 
@@ -23,17 +29,27 @@ fn main() -> Result<(), Box<dyn Error>> {
     }
 
     let addr: SocketAddr = arg.unwrap_or_default().parse()?;
-    let mut instance = ClientInstance::new(TcpStream::connect(addr)?);
+    let mut instance = ClientInstance::new(EncodedChannel::new(
+        TcpStream::connect(addr)?,
+        WireEncoding::MessagePack,
+    ))
+    // Only used to join the servers older than the handshake, which only speak JSON.
+    .with_reconnect(move || {
+        Ok(EncodedChannel::new(
+            TcpStream::connect(addr)?,
+            WireEncoding::Json,
+        ))
+    });
 
     instance.join(JoinMessageBody {
         difficulty: 2,
         player_id: None,
+        room: None,
+        pheromon_encoding: PheromonEncoding::QuantizedDelta,
     })?;
 
     instance.read_message()?;
 
-    let maze = instance.view.maze.clone();
-
     let background_instance = instance.backgroundify()?;
 
     let (rl, thread) = raylib::init()
@@ -78,13 +94,15 @@ fn main() -> Result<(), Box<dyn Error>> {
                 }))?;
         }
 
-        rl.begin_drawing(&thread, |d| draw(d, view, &maze))
+        rl.begin_drawing(&thread, |d| draw(d, view))
     }
 
     Ok(())
 }
 
-fn draw(d: RefMut<RaylibDrawHandle>, view: ClientGameView, maze: &Maze) {
+fn draw(d: RefMut<RaylibDrawHandle>, view: ClientGameView) {
+    let maze = &view.maze;
+
     d.clear_background(Color::WHITE);
 
     for x in 0..maze.nb_column {
@@ -93,6 +111,19 @@ fn draw(d: RefMut<RaylibDrawHandle>, view: ClientGameView, maze: &Maze) {
                 .get_tile(x, y)
                 .expect("Tried to render a incomplete maze");
 
+            // Hidden by the fog of war.
+            if tile.is_unknown() {
+                d.draw_rectangle(
+                    (x * TILE_WIDTH) as i32,
+                    (y * TILE_WIDTH) as i32,
+                    TILE_WIDTH as i32,
+                    TILE_WIDTH as i32,
+                    Color::DARKGRAY,
+                );
+
+                continue;
+            }
+
             if let Some(pheromon_level) = view.pheromon.get((x + y * maze.nb_column) as usize) {
                 d.draw_rectangle(
                     (x * TILE_WIDTH) as i32,
@@ -165,6 +196,19 @@ fn draw(d: RefMut<RaylibDrawHandle>, view: ClientGameView, maze: &Maze) {
         }
     }
 
+    for ant in view.ants.iter() {
+        d.draw_circle(
+            (ant.column * TILE_WIDTH + TILE_WIDTH / 2) as i32,
+            (ant.line * TILE_WIDTH + TILE_WIDTH / 2) as i32,
+            TILE_WIDTH as f32 / 4.0,
+            if ant.has_food {
+                Color::GREEN
+            } else {
+                TEAM_COLORS[ant.team as usize % TEAM_COLORS.len()]
+            },
+        );
+    }
+
     d.draw_circle(
         (view.player_position.0 * TILE_WIDTH + TILE_WIDTH / 2) as i32,
         (view.player_position.1 * TILE_WIDTH + TILE_WIDTH / 2) as i32,
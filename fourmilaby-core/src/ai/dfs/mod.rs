@@ -14,7 +14,12 @@ pub struct DfsAi {
 }
 
 impl AntAI for DfsAi {
-    fn step(&mut self, maze: &Maze, message: &InfoMessageBody) -> Option<MoveMessageBody> {
+    fn step(
+        &mut self,
+        _maze: &Maze,
+        _message: &InfoMessageBody,
+        _rng: &fastrand::Rng,
+    ) -> Option<MoveMessageBody> {
         todo!()
     }
 }
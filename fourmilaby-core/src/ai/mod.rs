@@ -15,19 +15,67 @@ use crate::{
     error::ServerError,
     game::{GameSessionMessage, GameSessionMessageKind},
     maze::Maze,
-    message::types::{InfoMessageBody, Message, MoveMessageBody},
+    message::types::{InfoMessageBody, Message, MoveMessageBody, PheromonEncoding},
 };
 
 /// A Ant AI.
 pub trait AntAI: Default + Send + 'static {
-    fn step(&mut self, maze: &Maze, message: &InfoMessageBody) -> Option<MoveMessageBody>;
+    /// Choose the next move of the ant, any randomness must come from `rng`.
+    fn step(
+        &mut self,
+        maze: &Maze,
+        message: &InfoMessageBody,
+        rng: &fastrand::Rng,
+    ) -> Option<MoveMessageBody>;
+}
+
+/// Artificial ants sharing what they know of the maze, without any connection to a game.
+pub struct Ants<AI: AntAI> {
+    /// The moves of a step are chosen in this order.
+    ants: Vec<(Uuid, AI)>,
+
+    /// Maze as known by the ants (depleted food sources are removed).
+    maze: Maze,
+    rng: fastrand::Rng,
+}
+
+impl<AI: AntAI> Ants<AI> {
+    pub fn new(uuids: &[Uuid], maze: Maze, rng: fastrand::Rng) -> Self {
+        Ants {
+            ants: uuids.iter().map(|uuid| (*uuid, AI::default())).collect(),
+            maze,
+            rng,
+        }
+    }
+
+    /// Let the ants know that the food source at (`column`, `line`) is depleted.
+    pub fn food_depleted(&mut self, column: u32, line: u32) {
+        if let Some(tile) = self.maze.get_tile_mut(column, line) {
+            tile.set_food(false);
+        }
+    }
+
+    /// Choose the next move of each ant, from its latest info given by `info` (if any).
+    pub fn step(
+        &mut self,
+        mut info: impl FnMut(&Uuid) -> Option<InfoMessageBody>,
+    ) -> Vec<(Uuid, MoveMessageBody)> {
+        self.ants
+            .iter_mut()
+            .filter_map(|(uuid, ai)| {
+                let movement = ai.step(&self.maze, &info(uuid)?, &self.rng)?;
+
+                Some((*uuid, movement))
+            })
+            .collect()
+    }
 }
 
 /// A group of artifical ants connected to a server.
 pub struct AntGroup<AI: AntAI> {
-    ants: HashMap<Uuid, (AI, Receiver<Message>)>,
+    ants: Ants<AI>,
+    receivers: HashMap<Uuid, Receiver<Message>>,
     game_channel: Sender<GameSessionMessage>,
-    maze: Maze,
 }
 
 impl<AI: AntAI> AntGroup<AI> {
@@ -36,46 +84,61 @@ impl<AI: AntAI> AntGroup<AI> {
         game_channel: Sender<GameSessionMessage>,
         maze: Maze,
     ) -> Result<Self, ServerError> {
-        let mut ants = HashMap::with_capacity(count);
+        let uuids: Vec<Uuid> = (0..count).map(|_| Uuid::new_v4()).collect();
+
+        Self::with_players(&uuids, game_channel, maze)
+    }
+
+    /// Create a group controlling the players `uuids`, reconnecting them if they already exist in the game.
+    pub fn with_players(
+        uuids: &[Uuid],
+        game_channel: Sender<GameSessionMessage>,
+        maze: Maze,
+    ) -> Result<Self, ServerError> {
+        let mut receivers = HashMap::with_capacity(uuids.len());
 
         // Create and connect all ants
-        for _ in 0..count {
-            let uuid = Uuid::new_v4();
+        for uuid in uuids.iter().copied() {
             let (sender, receiver) = mpsc::channel();
 
-            ants.insert(uuid, (AI::default(), receiver));
+            receivers.insert(uuid, receiver);
 
             game_channel.send(GameSessionMessage(
                 uuid,
-                GameSessionMessageKind::InitializePlayer(sender),
+                GameSessionMessageKind::InitializePlayer(sender, PheromonEncoding::Full, 0),
             ))?;
         }
 
         Ok(AntGroup {
-            ants,
+            ants: Ants::new(uuids, maze, fastrand::Rng::new()),
+            receivers,
             game_channel,
-            maze,
         })
     }
 
     pub fn step(&mut self) -> Result<(), ServerError> {
-        for (uuid, (ai, receiver)) in self.ants.iter_mut() {
-            // Get all latest messages.
-            let mut latest_message = None;
-            while let Ok(message) = receiver.try_recv() {
-                latest_message = Some(message);
-            }
+        let mut latest_info = HashMap::with_capacity(self.receivers.len());
 
-            if let Some(Message::Info(info)) = latest_message {
-                if let Some(movement) = ai.step(&self.maze, &info) {
-                    self.game_channel.send(GameSessionMessage(
-                        *uuid,
-                        GameSessionMessageKind::ClientMessage(Message::Move(movement)),
-                    ))?;
+        // Get all latest messages.
+        for (uuid, receiver) in self.receivers.iter() {
+            while let Ok(message) = receiver.try_recv() {
+                match message {
+                    Message::Info(info) => {
+                        latest_info.insert(*uuid, info);
+                    }
+                    Message::FoodDepleted(food) => self.ants.food_depleted(food.column, food.line),
+                    _ => (),
                 }
             }
         }
 
+        for (uuid, movement) in self.ants.step(|uuid| latest_info.remove(uuid)) {
+            self.game_channel.send(GameSessionMessage(
+                uuid,
+                GameSessionMessageKind::ClientMessage(Message::Move(movement)),
+            ))?;
+        }
+
         Ok(())
     }
 
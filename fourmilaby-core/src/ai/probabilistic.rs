@@ -11,7 +11,12 @@ use super::AntAI;
 pub struct ProbabilisticAnt;
 
 impl AntAI for ProbabilisticAnt {
-    fn step(&mut self, maze: &Maze, message: &InfoMessageBody) -> Option<MoveMessageBody> {
+    fn step(
+        &mut self,
+        maze: &Maze,
+        message: &InfoMessageBody,
+        rng: &fastrand::Rng,
+    ) -> Option<MoveMessageBody> {
         let pos = (message.player_column, message.player_line);
 
         let mut dirs = [
@@ -21,7 +26,7 @@ impl AntAI for ProbabilisticAnt {
             MoveDirection::East,
         ];
 
-        fastrand::shuffle(&mut dirs);
+        rng.shuffle(&mut dirs);
 
         if let Some(tile) = maze.get_tile(pos.0, pos.1) {
             for direction in dirs {
@@ -9,7 +9,13 @@ use std::{
 use crate::{
     error::ServerError,
     maze::Maze,
-    message::types::{JoinMessageBody, Message},
+    message::{
+        pheromon::apply_deltas,
+        types::{
+            AntBody, Capability, CreateRoomMessageBody, GameOverMessageBody, HelloMessageBody,
+            JoinMessageBody, Message, PheromonEncoding, PheromonLayerBody,
+        },
+    },
     protocols::PlayerChannel,
 };
 
@@ -19,7 +25,17 @@ pub struct ClientGameView {
     pub maze: Maze,
     pub player_position: (u32, u32),
     pub pheromon: Arc<Box<[f32]>>,
+    /// Additional pheromon layers sent by the server (e.g. the `home` trail).
+    pub pheromon_layers: Vec<PheromonLayerBody>,
     pub player_has_food: bool,
+    pub player_food_delivered: u32,
+    pub total_food_delivered: u32,
+
+    /// Other ants visible by the player.
+    pub ants: Vec<AntBody>,
+
+    /// Set when the game is over, the server closes the connection afterward.
+    pub game_over: Option<GameOverMessageBody>,
 }
 
 /// State of the client.
@@ -32,6 +48,9 @@ pub enum ClientState {
     Dead,
 }
 
+/// Open a new connection to the server, see [`ClientInstance::with_reconnect`].
+pub type Reconnect<C> = Box<dyn FnMut() -> Result<C, ServerError> + Send>;
+
 /// A client connection to a server.
 pub struct ClientInstance<C>
 where
@@ -41,6 +60,18 @@ where
     pub state: ClientState,
     pub player_uuid: Option<uuid::Uuid>,
     pub channel: C,
+
+    /// Answer of the server to the protocol handshake, see [`ClientInstance::hello`].
+    pub server_hello: Option<HelloMessageBody>,
+
+    /// Sender of the writing thread once backgroundified, the pongs are sent through it.
+    background_sender: Option<mpsc::Sender<Message>>,
+
+    /// Used to join the servers that don't know the hello, see [`ClientInstance::join`].
+    reconnect: Option<Reconnect<C>>,
+
+    /// The server refused the hello, it's older than the protocol handshake.
+    legacy_server: bool,
 }
 
 pub struct BackgroundClientInstance {
@@ -66,10 +97,79 @@ impl<C: PlayerChannel> ClientInstance<C> {
             state: ClientState::default(),
             player_uuid: None,
             channel,
+            server_hello: None,
+            background_sender: None,
+            reconnect: None,
+            legacy_server: false,
+        }
+    }
+
+    /// Set how to open a new connection to the server, needed to join the servers older than
+    /// the protocol handshake : they close the connection after refusing the hello.
+    pub fn with_reconnect(
+        mut self,
+        reconnect: impl FnMut() -> Result<C, ServerError> + Send + 'static,
+    ) -> Self {
+        self.reconnect = Some(Box::new(reconnect));
+        self
+    }
+
+    /// Negotiate the protocol version and capabilities with the server.
+    pub fn hello(&mut self) -> Result<&HelloMessageBody, ServerError> {
+        if !matches!(self.state, ClientState::Uninitialized) || self.server_hello.is_some() {
+            return ServerError::transmission_error(format!(
+                "Invalid state {:?} for hello operation.",
+                self.state
+            ));
+        }
+
+        self.channel
+            .write_message(&Message::Hello(HelloMessageBody::new()))?;
+
+        match self.channel.read_message()? {
+            Message::Hello(hello) => Ok(self.server_hello.insert(hello)),
+            // The server closes the connection after refusing the hello.
+            Message::Error(err) => {
+                self.state = ClientState::Dead;
+
+                Err(err)
+            }
+            Message::Unexpected { .. } => {
+                self.state = ClientState::Dead;
+
+                Err(ServerError::BadMessage(
+                    "Hello refused by the server.".into(),
+                ))
+            }
+            _ => ServerError::transmission_error("Unexpected message received during hello."),
         }
     }
 
-    pub fn join(&mut self, body: JoinMessageBody) -> Result<(), ServerError> {
+    /// Join a game, after negotiating the protocol if [`ClientInstance::hello`] wasn't called.
+    ///
+    /// If the server refuses the hello, the game is joined without it through a new connection
+    /// (see [`ClientInstance::with_reconnect`]), as the servers older than the handshake expect.
+    pub fn join(&mut self, mut body: JoinMessageBody) -> Result<(), ServerError> {
+        if self.server_hello.is_none() && !self.legacy_server {
+            if let Err(err) = self.hello() {
+                // The server didn't answer, or there is no way to reach it again.
+                if !matches!(self.state, ClientState::Dead) {
+                    return Err(err);
+                }
+
+                let Some(reconnect) = self.reconnect.as_mut() else {
+                    return Err(err);
+                };
+
+                let channel = reconnect()?;
+
+                self.channel.stop().ok();
+                self.channel = channel;
+                self.state = ClientState::Uninitialized;
+                self.legacy_server = true;
+            }
+        }
+
         if !matches!(self.state, ClientState::Uninitialized) {
             return ServerError::transmission_error(format!(
                 "Invalid state {:?} for join operation.",
@@ -77,6 +177,16 @@ impl<C: PlayerChannel> ClientInstance<C> {
             ));
         }
 
+        // Fall back to the whole pheromon grids if the server can't send deltas.
+        if self.legacy_server
+            || self
+                .server_hello
+                .as_ref()
+                .is_some_and(|hello| !hello.supports(Capability::PheromonDeltas))
+        {
+            body.pheromon_encoding = PheromonEncoding::Full;
+        }
+
         self.channel
             .write_message(&Message::Join(body))
             .and_then(|_| {
@@ -89,6 +199,24 @@ impl<C: PlayerChannel> ClientInstance<C> {
             })
     }
 
+    /// Create a private room, returns the room code to put in [`JoinMessageBody::room`].
+    pub fn create_room(&mut self, body: CreateRoomMessageBody) -> Result<Box<str>, ServerError> {
+        if !matches!(self.state, ClientState::Uninitialized) {
+            return ServerError::transmission_error(format!(
+                "Invalid state {:?} for create room operation.",
+                self.state
+            ));
+        }
+
+        self.channel.write_message(&Message::CreateRoom(body))?;
+
+        match self.channel.read_message()? {
+            Message::RoomCreated(room) => Ok(room.code),
+            Message::Error(err) => Err(err),
+            _ => ServerError::transmission_error("Unexpected message received when creating room."),
+        }
+    }
+
     pub fn read_message(&mut self) -> Result<(), ServerError> {
         // Some early checks on state.
         match self.state {
@@ -123,19 +251,59 @@ impl<C: PlayerChannel> ClientInstance<C> {
             }
             ClientState::Joined => {
                 // Check next message
-                if let Message::Info(info) = message {
-                    // Update view
-                    self.view.player_position = (info.player_column, info.player_line);
-                    self.view.pheromon = info.pheromon;
-                    self.view.player_has_food = info.player_has_food;
+                match message {
+                    Message::Info(info) => {
+                        // Update view
+                        self.view.player_position = (info.player_column, info.player_line);
+                        // Empty grids when the server only sent the changes.
+                        if info.pheromon.is_empty() {
+                            apply_deltas(
+                                &info.pheromon_deltas,
+                                &mut self.view.pheromon,
+                                &mut self.view.pheromon_layers,
+                            );
+                        } else {
+                            self.view.pheromon = info.pheromon;
+                            self.view.pheromon_layers = info.pheromon_layers;
+                        }
 
-                    Ok(())
-                } else {
-                    self.state = ClientState::Dead;
+                        self.view.player_has_food = info.player_has_food;
+                        self.view.player_food_delivered = info.player_food_delivered;
+                        self.view.total_food_delivered = info.total_food_delivered;
+                        self.view.ants = info.ants;
+
+                        // Reveal the perceived tiles (fog of war).
+                        for tile in info.tiles {
+                            self.view
+                                .maze
+                                .set_tile(tile.column, tile.line, &tile.tile.into());
+                        }
+
+                        Ok(())
+                    }
+                    Message::FoodDepleted(food) => {
+                        if let Some(tile) = self.view.maze.get_tile_mut(food.column, food.line) {
+                            tile.set_food(false);
+                        }
+
+                        Ok(())
+                    }
+                    Message::GameOver(game_over) => {
+                        self.view.game_over = Some(game_over);
+
+                        Ok(())
+                    }
+                    Message::Ping(ping) => match &self.background_sender {
+                        Some(sender) => Ok(sender.send(Message::Pong(ping))?),
+                        None => self.channel.write_message(&Message::Pong(ping)),
+                    },
+                    _ => {
+                        self.state = ClientState::Dead;
 
-                    ServerError::transmission_error(
-                        "Unexpected message received during info fetching.",
-                    )
+                        ServerError::transmission_error(
+                            "Unexpected message received during info fetching.",
+                        )
+                    }
                 }
             }
             ClientState::Uninitialized | ClientState::Dead => unreachable!(),
@@ -149,6 +317,7 @@ impl<C: PlayerChannel> ClientInstance<C> {
         }
 
         let move_channels = mpsc::channel();
+        self.background_sender = Some(move_channels.0.clone());
 
         let mut receiver_channel = self.channel.clone_instance();
         let receiver_thread = thread::Builder::new()
@@ -173,8 +342,16 @@ impl<C: PlayerChannel> ClientInstance<C> {
                     let mut new_view = thread_view.lock()?;
 
                     new_view.pheromon = self.view.pheromon.clone();
+                    new_view
+                        .pheromon_layers
+                        .clone_from(&self.view.pheromon_layers);
                     new_view.player_position = self.view.player_position;
                     new_view.player_has_food = self.view.player_has_food;
+                    new_view.player_food_delivered = self.view.player_food_delivered;
+                    new_view.total_food_delivered = self.view.total_food_delivered;
+                    new_view.ants.clone_from(&self.view.ants);
+                    new_view.maze.clone_from(&self.view.maze);
+                    new_view.game_over.clone_from(&self.view.game_over);
                 }
             })?;
 
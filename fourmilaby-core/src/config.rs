@@ -1,20 +1,22 @@
 //! Lobby and game configuration.
 use std::{
     fs,
-    net::{IpAddr, Ipv4Addr},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::PathBuf,
 };
 
 use serde::{Deserialize, Serialize};
 
 use crate::error::ServerError;
 
-#[derive(Copy, Clone, Serialize, Deserialize)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum NestPositioning {
     Randomized,
     Fixed(u32, u32),
 }
 
-#[derive(Copy, Clone, Serialize, Deserialize)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct GeneratorConfig {
     pub column_min: u32,
     pub column_coeff: f32,
@@ -26,6 +28,9 @@ pub struct GeneratorConfig {
 
     pub basic_generator_size: u32,
     pub nest_pos: NestPositioning,
+
+    /// Amount of nests (and so teams), the first one is placed using `nest_pos`.
+    pub nb_nests: u32,
 }
 
 impl Default for GeneratorConfig {
@@ -41,53 +46,370 @@ impl Default for GeneratorConfig {
             carving_amount: 2,
 
             nest_pos: NestPositioning::Fixed(1, 1),
+            nb_nests: 1,
         }
     }
 }
 
-#[derive(Copy, Clone, Serialize, Deserialize)]
+/// Conditions that end a game, the game ends as soon as one of them is met.
+#[derive(Copy, Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EndConditions {
+    /// Duration of the game (in seconds).
+    pub time_limit: Option<u64>,
+
+    /// Amount of food to bring back to the nest (all players included).
+    pub food_goal: Option<u32>,
+
+    /// End the game once all the food has been brought back to the nest.
+    pub all_food_depleted: bool,
+}
+
+/// Rules of a pheromon layer.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct PheromonLayerConfig {
+    /// Amount of pheromon added on a tile each time a player walks on it.
+    pub drop_amount: f32,
+
+    /// Part of the pheromon that evaporates on each update.
+    pub evaporation_rate: f32,
+}
+
+/// Rules of the pheromon.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PheromonConfig {
+    /// Trail dropped by the players carrying food.
+    pub food: PheromonLayerConfig,
+
+    /// Trail dropped by the players searching for food.
+    pub home: PheromonLayerConfig,
+
+    /// Part of the pheromon of each tile spread to its wall-free neighbours on each update,
+    /// `0` disables the diffusion.
+    pub diffusion_rate: f32,
+
+    /// Delay between each pheromon update (in milliseconds).
+    pub update_delay: u64,
+}
+
+impl Default for PheromonConfig {
+    fn default() -> Self {
+        Self {
+            food: PheromonLayerConfig {
+                drop_amount: 0.2,
+                evaporation_rate: 0.1,
+            },
+            home: PheromonLayerConfig {
+                drop_amount: 0.1,
+                evaporation_rate: 0.1,
+            },
+            diffusion_rate: 0.0,
+            update_delay: 5000,
+        }
+    }
+}
+
+/// Tick-based simulation, moves are queued and resolved once per tick.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TickConfig {
+    /// Duration of a tick (in milliseconds).
+    pub tick_duration: u64,
+
+    /// Amount of ticks between each pheromon update, `0` disables the pheromon updates.
+    pub pheromon_update_ticks: u32,
+}
+
+impl Default for TickConfig {
+    fn default() -> Self {
+        Self {
+            tick_duration: 100,
+            pheromon_update_ticks: 50,
+        }
+    }
+}
+
+/// Which other ants a player sees in its info messages.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum AntVisibility {
+    /// The other ants are never sent.
+    Hidden,
+    /// The whole colony is sent, all teams included.
+    Colony,
+    /// Only the ants within the given distance (in tiles, Manhattan distance) are sent.
+    Nearby(u32),
+}
+
+/// Fog of war, the players only perceive the tiles around their ant.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FogOfWarConfig {
+    /// Perception distance (in tiles, Manhattan distance).
+    pub radius: u32,
+
+    /// The walls block the perception : only the tiles reachable within `radius` moves are seen.
+    pub line_of_sight: bool,
+}
+
+impl Default for FogOfWarConfig {
+    fn default() -> Self {
+        Self {
+            radius: 2,
+            line_of_sight: true,
+        }
+    }
+}
+
+/// Rules of a game.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GameConfig {
+    /// Amount of food each food source holds, `None` for endless food sources.
+    pub food_quantity: Option<u32>,
+
+    pub end_conditions: EndConditions,
+    pub pheromon: PheromonConfig,
+
+    /// Run the game in tick-based mode instead of processing the moves as they arrive.
+    pub tick: Option<TickConfig>,
+
+    /// Other ants sent to the players, see [`crate::message::types::InfoMessageBody::ants`].
+    pub ant_visibility: AntVisibility,
+
+    /// Hide the maze and the pheromon outside of the perception of the players, `None` to disable.
+    pub fog_of_war: Option<FogOfWarConfig>,
+}
+
+impl GameConfig {
+    /// Check that the game can run with these rules.
+    pub fn validate(&self) -> Result<(), ServerError> {
+        if self.pheromon.update_delay == 0 {
+            return Err(ServerError::Other(
+                "Invalid config : pheromon.update_delay must be at least 1 ms".into(),
+            ));
+        }
+
+        if self.tick.is_some_and(|tick| tick.tick_duration == 0) {
+            return Err(ServerError::Other(
+                "Invalid config : tick.tick_duration must be at least 1 ms".into(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        Self {
+            food_quantity: Some(10),
+            end_conditions: Default::default(),
+            pheromon: Default::default(),
+            tick: None,
+            ant_visibility: AntVisibility::Colony,
+            fog_of_war: None,
+        }
+    }
+}
+
+/// Keep-alive of the game connections, see [`crate::message::types::Message::Ping`].
+///
+/// Only the clients that negotiated [`crate::message::types::Capability::Heartbeat`] are pinged
+/// and disconnected when silent, the legacy clients can't answer the pings.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HeartbeatConfig {
+    /// Delay between each ping sent to a client (in milliseconds).
+    pub ping_delay: u64,
+
+    /// Disconnect a client after receiving nothing from it for this long (in milliseconds),
+    /// it can still reconnect to its game afterward.
+    pub timeout: u64,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        Self {
+            ping_delay: 5000,
+            timeout: 15000,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct LobbyConfig {
     pub record_games: bool,
+
+    /// Directory where the game records are written, see [`crate::game::record`].
+    pub record_directory: PathBuf,
+
     pub generator: GeneratorConfig,
+    pub game: GameConfig,
+
+    /// Maximum amount of players (AI excluded) that can join a game through matchmaking.
+    pub max_players: u32,
+
+    /// Seed of the lobby random generator (mazes, room codes), random if `None`.
+    pub seed: Option<u64>,
+
+    /// Directory where the running games are snapshotted, to restore them on the next start.
+    /// `None` disables the snapshots.
+    pub snapshot_directory: Option<PathBuf>,
+
+    /// Delay between each snapshot of a game (in seconds).
+    pub snapshot_delay: u64,
+
+    /// Ping the clients and disconnect the silent ones, `None` to disable.
+    pub heartbeat: Option<HeartbeatConfig>,
 }
 
 impl Default for LobbyConfig {
     fn default() -> Self {
         Self {
             record_games: false,
+            record_directory: PathBuf::from("records"),
             generator: Default::default(),
+            game: Default::default(),
+            max_players: 8,
+            seed: None,
+            snapshot_directory: None,
+            snapshot_delay: 30,
+            heartbeat: Some(Default::default()),
         }
     }
 }
 
+/// An address the server listens on, along with the protocol used to accept clients.
+#[derive(Clone, Serialize, Deserialize)]
+pub enum BindConfig {
+    /// Raw TCP, see [`crate::protocols::tcp`].
+    Tcp(SocketAddr),
+    /// Websocket, see [`crate::protocols::tungstenite`].
+    WebSocket(SocketAddr),
+    /// Unix domain socket (Unix only), see [`crate::protocols::unix`].
+    Unix(PathBuf),
+}
+
 #[derive(Serialize, Deserialize)]
+#[serde(from = "ServerConfigFile")]
 pub struct ServerConfig {
-    pub ip: IpAddr,
-    pub port: u16,
+    /// Addresses the server listens on, only TCP on port 8080 by default.
+    /// Add e.g. `{ "WebSocket": "0.0.0.0:8081" }` or `{ "Unix": "fourmilaby.sock" }`
+    /// to accept the websocket or Unix socket clients too.
+    pub binds: Vec<BindConfig>,
     pub lobby: LobbyConfig,
 }
 
+/// [`ServerConfig`] as read from the file, also accepting the legacy `ip` and `port` fields
+/// of the configurations written before [`BindConfig`].
+#[derive(Deserialize)]
+struct ServerConfigFile {
+    #[serde(default)]
+    binds: Vec<BindConfig>,
+    ip: Option<IpAddr>,
+    port: Option<u16>,
+    lobby: LobbyConfig,
+}
+
+impl From<ServerConfigFile> for ServerConfig {
+    fn from(file: ServerConfigFile) -> Self {
+        let mut binds = file.binds;
+
+        // The legacy configurations listened on a single TCP address.
+        if file.ip.is_some() || file.port.is_some() {
+            binds.push(BindConfig::Tcp(SocketAddr::new(
+                file.ip.unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+                file.port.unwrap_or(8080),
+            )));
+        }
+
+        if binds.is_empty() {
+            binds = Self::default().binds;
+        }
+
+        Self {
+            binds,
+            lobby: file.lobby,
+        }
+    }
+}
+
 impl Default for ServerConfig {
     fn default() -> Self {
         Self {
-            ip: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
-            port: 8080,
+            binds: vec![BindConfig::Tcp(SocketAddr::new(
+                IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                8080,
+            ))],
             lobby: Default::default(),
         }
     }
 }
 
+/// Configuration of a batch of headless simulations, see [`crate::simulation`].
+#[derive(Copy, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SimulationConfig {
+    pub generator: GeneratorConfig,
+    pub game: GameConfig,
+    pub difficulty: u32,
+
+    /// Amount of AI ants in each game.
+    pub ants: u32,
+
+    /// Maximum amount of steps (ticks) of a game, if no end condition is met before.
+    pub max_steps: u64,
+
+    /// Amount of games to simulate, game `i` uses the seed `seed + i`.
+    pub games: u32,
+    pub seed: u64,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        Self {
+            generator: Default::default(),
+            game: Default::default(),
+            difficulty: 1,
+            ants: 10,
+            max_steps: 10_000,
+            games: 100,
+            seed: 0,
+        }
+    }
+}
+
 const DEFAULT_CONFIG_PATH: &str = "config.json";
+const DEFAULT_SIMULATION_CONFIG_PATH: &str = "simulation.json";
 
 pub fn load_config(config_path: Option<&str>) -> Result<ServerConfig, ServerError> {
-    let path_str = config_path.unwrap_or(DEFAULT_CONFIG_PATH);
+    let config: ServerConfig = load_or_regenerate(config_path.unwrap_or(DEFAULT_CONFIG_PATH))?;
+    config.lobby.game.validate()?;
+
+    Ok(config)
+}
 
+pub fn load_simulation_config(config_path: Option<&str>) -> Result<SimulationConfig, ServerError> {
+    let config: SimulationConfig =
+        load_or_regenerate(config_path.unwrap_or(DEFAULT_SIMULATION_CONFIG_PATH))?;
+    config.game.validate()?;
+
+    Ok(config)
+}
+
+/// Load a configuration file, regenerate it with the default configuration if it can't be read.
+fn load_or_regenerate<T>(path_str: &str) -> Result<T, ServerError>
+where
+    T: Default + Serialize + for<'de> Deserialize<'de>,
+{
     match fs::read_to_string(path_str) {
-        Ok(content) => Ok(serde_json::from_str::<ServerConfig>(content.as_str())?),
+        Ok(content) => Ok(serde_json::from_str::<T>(content.as_str())?),
         Err(err) => {
             println!("Unable to read config ({err}), regenerate config.");
 
-            let config = ServerConfig::default();
+            let config = T::default();
 
             fs::write(path_str, serde_json::to_string_pretty(&config).unwrap())
                 .expect("Unable to regenerate config file.");
@@ -96,3 +418,64 @@ pub fn load_config(config_path: Option<&str>) -> Result<ServerConfig, ServerErro
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_server_config() {
+        let config: ServerConfig = serde_json::from_str(
+            r#"{
+                "ip": "127.0.0.1",
+                "port": 9000,
+                "lobby": {
+                    "record_games": true,
+                    "generator": {
+                        "column_min": 5,
+                        "column_coeff": 3.0,
+                        "line_min": 4,
+                        "line_coeff": 3.0,
+                        "nb_food_min": 1,
+                        "nb_food_coeff": 0.25,
+                        "carving_amount": 2,
+                        "basic_generator_size": 6,
+                        "nest_pos": { "Fixed": [1, 1] }
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            config.binds.as_slice(),
+            [BindConfig::Tcp(addr)] if *addr == "127.0.0.1:9000".parse().unwrap()
+        ));
+        assert!(config.lobby.record_games);
+    }
+
+    #[test]
+    fn server_config_round_trip() {
+        let config = ServerConfig::default();
+        let loaded: ServerConfig =
+            serde_json::from_str(&serde_json::to_string(&config).unwrap()).unwrap();
+
+        assert_eq!(loaded.binds.len(), config.binds.len());
+    }
+
+    #[test]
+    fn zero_delays_rejected() {
+        let mut config = GameConfig::default();
+        assert!(config.validate().is_ok());
+
+        config.pheromon.update_delay = 0;
+        assert!(config.validate().is_err());
+
+        config.pheromon.update_delay = 1;
+        config.tick = Some(TickConfig {
+            tick_duration: 0,
+            ..Default::default()
+        });
+        assert!(config.validate().is_err());
+    }
+}
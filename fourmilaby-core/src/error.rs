@@ -1,10 +1,16 @@
 //! Manages the [`ServerError`] type that handle all kind of errors that may happen.
 use std::{error::Error, fmt::Display};
 
+use schemars::{gen::SchemaGenerator, schema::Schema, JsonSchema};
 use serde::{Deserialize, Serialize};
 
+use crate::message::types::Capability;
+
 /// Represents any error that can happen on the server.
+///
+/// Sent to the clients as an [`ErrorBody`], see [`ServerError::code`].
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(into = "ErrorBody", from = "ReceivedError")]
 pub enum ServerError {
     InvalidMaze(Box<str>),
     Transmission(Box<str>),
@@ -12,9 +18,135 @@ pub enum ServerError {
     AlreadyConnected,
     UnexpectedParameter,
     Other(Box<str>),
+
+    /// The protocol version of the client isn't supported,
+    /// the server supports the versions from `min_version` to `max_version`.
+    IncompatibleVersion {
+        version: u32,
+        min_version: u32,
+        max_version: u32,
+    },
+
+    /// Nothing has been received from the remote instance for too long.
+    TimedOut,
+    /// A message couldn't be decoded, or wasn't expected.
+    BadMessage(Box<str>),
+    /// The player UUID is unknown, or its game is over.
+    ExpiredUuid,
+    /// The room code is unknown, or its game is over.
+    UnknownRoom,
+    /// There is no room left for another player in the game.
+    GameFull,
+    /// There is no record of the game with this UUID.
+    UnknownRecord(uuid::Uuid),
+    /// The game of the room code or player UUID needs a capability the client didn't negotiate.
+    MissingCapability(Capability),
+}
+
+/// Stable identifier of a [`ServerError`], for the clients to branch on.
+///
+/// Codes are never renamed nor reused, new ones may be added by newer servers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum ErrorCode {
+    InvalidMaze,
+    Transmission,
+    Serialization,
+    AlreadyConnected,
+    UnexpectedParameter,
+    /// Internal server failure, see [`ServerError::Other`].
+    Internal,
+    IncompatibleVersion,
+    TimedOut,
+    BadMessage,
+    ExpiredUuid,
+    UnknownRoom,
+    GameFull,
+    UnknownRecord,
+    MissingCapability,
+    /// A code unknown to this implementation (e.g. from a newer version).
+    #[serde(other)]
+    Unknown,
+}
+
+/// A [`ServerError`] as sent to the clients.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorBody {
+    pub code: ErrorCode,
+
+    /// Human readable description of the error, not meant to be parsed.
+    pub message: Box<str>,
+
+    /// Data specific to the error code, see [`VersionDetails`], [`RecordDetails`]
+    /// and [`CapabilityDetails`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub details: Option<serde_json::Value>,
+}
+
+/// A [`ServerError`] as received, the servers older than [`ErrorBody`] send a [`LegacyError`].
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ReceivedError {
+    Body(ErrorBody),
+    Legacy(LegacyError),
+}
+
+/// A [`ServerError`] as sent by the servers older than [`ErrorBody`].
+#[derive(Deserialize)]
+enum LegacyError {
+    InvalidMaze(Box<str>),
+    Transmission(Box<str>),
+    SerializerError(Box<str>),
+    AlreadyConnected,
+    UnexpectedParameter,
+    Other(Box<str>),
+}
+
+/// Details of an [`ErrorCode::IncompatibleVersion`] error.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct VersionDetails {
+    pub version: u32,
+    pub min_version: u32,
+    pub max_version: u32,
+}
+
+/// Details of an [`ErrorCode::UnknownRecord`] error.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordDetails {
+    pub id: uuid::Uuid,
+}
+
+/// Details of an [`ErrorCode::MissingCapability`] error.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CapabilityDetails {
+    pub capability: Capability,
 }
 
 impl ServerError {
+    /// Get the [`ErrorCode`] of the error.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            ServerError::InvalidMaze(_) => ErrorCode::InvalidMaze,
+            ServerError::Transmission(_) => ErrorCode::Transmission,
+            ServerError::SerializerError(_) => ErrorCode::Serialization,
+            ServerError::AlreadyConnected => ErrorCode::AlreadyConnected,
+            ServerError::UnexpectedParameter => ErrorCode::UnexpectedParameter,
+            ServerError::Other(_) => ErrorCode::Internal,
+            ServerError::IncompatibleVersion { .. } => ErrorCode::IncompatibleVersion,
+            ServerError::TimedOut => ErrorCode::TimedOut,
+            ServerError::BadMessage(_) => ErrorCode::BadMessage,
+            ServerError::ExpiredUuid => ErrorCode::ExpiredUuid,
+            ServerError::UnknownRoom => ErrorCode::UnknownRoom,
+            ServerError::GameFull => ErrorCode::GameFull,
+            ServerError::UnknownRecord(_) => ErrorCode::UnknownRecord,
+            ServerError::MissingCapability(_) => ErrorCode::MissingCapability,
+        }
+    }
+
     /// Create a [`ServerError::Transmission`]  [`ServerError`].
     pub fn transmission<S>(str: S) -> Self
     where
@@ -64,15 +196,146 @@ impl ServerError {
     }
 }
 
+// Sent as an `ErrorBody`, see the serde attributes of `ServerError`.
+impl JsonSchema for ServerError {
+    fn schema_name() -> String {
+        ErrorBody::schema_name()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        ErrorBody::json_schema(gen)
+    }
+}
+
+impl From<ServerError> for ErrorBody {
+    fn from(err: ServerError) -> Self {
+        let details = match &err {
+            ServerError::IncompatibleVersion {
+                version,
+                min_version,
+                max_version,
+            } => serde_json::to_value(VersionDetails {
+                version: *version,
+                min_version: *min_version,
+                max_version: *max_version,
+            })
+            .ok(),
+            ServerError::UnknownRecord(id) => serde_json::to_value(RecordDetails { id: *id }).ok(),
+            ServerError::MissingCapability(capability) => serde_json::to_value(CapabilityDetails {
+                capability: *capability,
+            })
+            .ok(),
+            _ => None,
+        };
+
+        ErrorBody {
+            code: err.code(),
+            message: match err {
+                ServerError::InvalidMaze(msg)
+                | ServerError::Transmission(msg)
+                | ServerError::SerializerError(msg)
+                | ServerError::Other(msg)
+                | ServerError::BadMessage(msg) => msg,
+                err => err.to_string().into(),
+            },
+            details,
+        }
+    }
+}
+
+impl From<ErrorBody> for ServerError {
+    fn from(body: ErrorBody) -> Self {
+        let details = body.details.unwrap_or_default();
+
+        match body.code {
+            ErrorCode::InvalidMaze => ServerError::InvalidMaze(body.message),
+            ErrorCode::Transmission => ServerError::Transmission(body.message),
+            ErrorCode::Serialization => ServerError::SerializerError(body.message),
+            ErrorCode::AlreadyConnected => ServerError::AlreadyConnected,
+            ErrorCode::UnexpectedParameter => ServerError::UnexpectedParameter,
+            ErrorCode::TimedOut => ServerError::TimedOut,
+            ErrorCode::BadMessage => ServerError::BadMessage(body.message),
+            ErrorCode::ExpiredUuid => ServerError::ExpiredUuid,
+            ErrorCode::UnknownRoom => ServerError::UnknownRoom,
+            ErrorCode::GameFull => ServerError::GameFull,
+            ErrorCode::IncompatibleVersion => match serde_json::from_value(details) {
+                Ok(VersionDetails {
+                    version,
+                    min_version,
+                    max_version,
+                }) => ServerError::IncompatibleVersion {
+                    version,
+                    min_version,
+                    max_version,
+                },
+                Err(_) => ServerError::Other(body.message),
+            },
+            ErrorCode::UnknownRecord => match serde_json::from_value(details) {
+                Ok(RecordDetails { id }) => ServerError::UnknownRecord(id),
+                Err(_) => ServerError::Other(body.message),
+            },
+            ErrorCode::MissingCapability => match serde_json::from_value(details) {
+                Ok(CapabilityDetails { capability }) => ServerError::MissingCapability(capability),
+                Err(_) => ServerError::Other(body.message),
+            },
+            // Keep the message of the errors we can't identify.
+            ErrorCode::Internal | ErrorCode::Unknown => ServerError::Other(body.message),
+        }
+    }
+}
+
+impl From<ReceivedError> for ServerError {
+    fn from(error: ReceivedError) -> Self {
+        match error {
+            ReceivedError::Body(body) => body.into(),
+            ReceivedError::Legacy(LegacyError::InvalidMaze(msg)) => ServerError::InvalidMaze(msg),
+            ReceivedError::Legacy(LegacyError::Transmission(msg)) => ServerError::Transmission(msg),
+            ReceivedError::Legacy(LegacyError::SerializerError(msg)) => {
+                ServerError::SerializerError(msg)
+            }
+            ReceivedError::Legacy(LegacyError::AlreadyConnected) => ServerError::AlreadyConnected,
+            ReceivedError::Legacy(LegacyError::UnexpectedParameter) => {
+                ServerError::UnexpectedParameter
+            }
+            ReceivedError::Legacy(LegacyError::Other(msg)) => ServerError::Other(msg),
+        }
+    }
+}
+
 impl From<serde_json::Error> for ServerError {
     fn from(err: serde_json::Error) -> Self {
         ServerError::SerializerError(err.to_string().into_boxed_str())
     }
 }
 
+impl From<rmp_serde::encode::Error> for ServerError {
+    fn from(err: rmp_serde::encode::Error) -> Self {
+        ServerError::SerializerError(err.to_string().into_boxed_str())
+    }
+}
+
+impl From<rmp_serde::decode::Error> for ServerError {
+    fn from(err: rmp_serde::decode::Error) -> Self {
+        ServerError::SerializerError(err.to_string().into_boxed_str())
+    }
+}
+
 impl From<std::io::Error> for ServerError {
     fn from(err: std::io::Error) -> Self {
-        ServerError::Transmission(err.to_string().into_boxed_str())
+        match err.kind() {
+            // Read timeouts are reported as either of them, depending on the platform.
+            std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut => ServerError::TimedOut,
+            _ => ServerError::Transmission(err.to_string().into_boxed_str()),
+        }
+    }
+}
+
+impl From<tungstenite::Error> for ServerError {
+    fn from(err: tungstenite::Error) -> Self {
+        match err {
+            tungstenite::Error::Io(err) => err.into(),
+            err => ServerError::Transmission(err.to_string().into_boxed_str()),
+        }
     }
 }
 
@@ -105,8 +368,53 @@ impl Display for ServerError {
             ServerError::AlreadyConnected => {
                 write!(f, "A client with this UUID is already connected !")
             }
+            ServerError::IncompatibleVersion {
+                version,
+                min_version,
+                max_version,
+            } => write!(
+                f,
+                "Incompatible protocol version {version} (supported: {min_version} to {max_version})"
+            ),
+            ServerError::TimedOut => write!(f, "Timed out"),
+            ServerError::BadMessage(msg) => write!(f, "Bad message: {msg}"),
+            ServerError::ExpiredUuid => write!(f, "Invalid UUID or game doesn't exist anymore."),
+            ServerError::UnknownRoom => write!(f, "Unknown room or room doesn't exist anymore."),
+            ServerError::GameFull => write!(f, "Game is full."),
+            ServerError::UnknownRecord(id) => write!(f, "Unknown record {id}"),
+            ServerError::MissingCapability(capability) => {
+                write!(f, "The game needs the {capability:?} capability.")
+            }
         }
     }
 }
 
 impl Error for ServerError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_round_trip() {
+        let error = ServerError::MissingCapability(Capability::FogOfWar);
+        let json = serde_json::to_string(&error).unwrap();
+
+        assert!(matches!(
+            serde_json::from_str(&json).unwrap(),
+            ServerError::MissingCapability(Capability::FogOfWar)
+        ));
+    }
+
+    #[test]
+    fn legacy_error() {
+        assert!(matches!(
+            serde_json::from_str(r#"{"Transmission": "Unexpected message received"}"#).unwrap(),
+            ServerError::Transmission(msg) if &*msg == "Unexpected message received"
+        ));
+        assert!(matches!(
+            serde_json::from_str(r#""AlreadyConnected""#).unwrap(),
+            ServerError::AlreadyConnected
+        ));
+    }
+}
@@ -0,0 +1,172 @@
+//! Fog of war, the players only perceive the tiles around their ant.
+//!
+//! The server never sends what a player doesn't perceive : the maze sent when joining is
+//! [`Maze::fogged`], then each info message holds the perceived tiles and the pheromon of
+//! these tiles only. The tiles revealed by each player are remembered by the [`GameState`],
+//! so that a reconnecting player gets back its knowledge of the maze.
+use std::{collections::VecDeque, sync::Arc};
+
+use uuid::Uuid;
+
+use crate::{
+    config::FogOfWarConfig,
+    maze::{Maze, Tile},
+    message::types::{MoveDirection, TileBody},
+};
+
+use super::state::{GameState, PlayerInfo};
+
+/// Get the indices (see [`Maze::tiles`]) of the tiles perceived from `position`.
+pub fn perceived_tiles(maze: &Maze, position: (u32, u32), config: &FogOfWarConfig) -> Vec<usize> {
+    let index = |(column, line): (u32, u32)| (column + line * maze.nb_column) as usize;
+
+    if maze.get_tile(position.0, position.1).is_none() {
+        return vec![];
+    }
+
+    if !config.line_of_sight {
+        let radius = config.radius;
+
+        let lines = position.1.saturating_sub(radius)
+            ..=position.1.saturating_add(radius).min(maze.nb_line - 1);
+        let columns = position.0.saturating_sub(radius)
+            ..=position.0.saturating_add(radius).min(maze.nb_column - 1);
+
+        return lines
+            .flat_map(|line| columns.clone().map(move |column| (column, line)))
+            .filter(|(column, line)| {
+                column.abs_diff(position.0) + line.abs_diff(position.1) <= radius
+            })
+            .map(index)
+            .collect();
+    }
+
+    // Walk through the open walls, up to `radius` moves away.
+    let mut perceived = vec![index(position)];
+    let mut queue = VecDeque::from([(position, 0)]);
+
+    while let Some((position, distance)) = queue.pop_front() {
+        if distance >= config.radius {
+            continue;
+        }
+
+        let tile = match maze.get_tile(position.0, position.1) {
+            Some(tile) => tile,
+            None => continue,
+        };
+
+        for neighbour in tile
+            .get_walkable_directions()
+            .into_iter()
+            .filter_map(|direction| MoveDirection::apply_movement(direction, position))
+            .filter(|(column, line)| maze.get_tile(*column, *line).is_some())
+        {
+            if !perceived.contains(&index(neighbour)) {
+                perceived.push(index(neighbour));
+                queue.push_back((neighbour, distance + 1));
+            }
+        }
+    }
+
+    perceived
+}
+
+impl GameState {
+    /// Get the indices of the tiles perceived by the player, `None` without fog of war.
+    pub fn perception(&self, info: &PlayerInfo) -> Option<Vec<usize>> {
+        self.config
+            .fog_of_war
+            .map(|config| perceived_tiles(&self.maze, info.position, &config))
+    }
+
+    /// Remember the tiles currently perceived by the player.
+    pub(super) fn reveal(&mut self, uuid: &Uuid) {
+        let perceived = match self.players.get(uuid) {
+            Some(info) => self.perception(info),
+            None => None,
+        };
+
+        if let Some(perceived) = perceived {
+            let tile_count = self.maze.tiles.len();
+            let revealed = self
+                .revealed
+                .entry(*uuid)
+                .or_insert_with(|| vec![false; tile_count].into_boxed_slice());
+
+            for index in perceived {
+                if let Some(revealed) = revealed.get_mut(index) {
+                    *revealed = true;
+                }
+            }
+        }
+    }
+
+    /// Check if the player revealed the tile at `position`, always `true` without fog of war.
+    pub fn is_revealed(&self, uuid: &Uuid, position: (u32, u32)) -> bool {
+        if self.config.fog_of_war.is_none() {
+            return true;
+        }
+
+        let index = (position.0 + position.1 * self.maze.nb_column) as usize;
+
+        self.revealed
+            .get(uuid)
+            .and_then(|revealed| revealed.get(index))
+            .copied()
+            .unwrap_or(false)
+    }
+
+    /// Get all the tiles revealed by the player.
+    pub fn revealed_tiles(&self, uuid: &Uuid) -> Vec<TileBody> {
+        self.revealed
+            .get(uuid)
+            .map(|revealed| {
+                revealed
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, revealed)| **revealed)
+                    .map(|(index, _)| self.tile_body(index))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Get the tile at `index` as currently known, the depleted food sources are removed.
+    pub(super) fn tile_body(&self, index: usize) -> TileBody {
+        let mut tile = Tile::from(self.maze.tiles[index]);
+
+        if self.food.get(index) == Some(&0) {
+            tile.set_food(false);
+        }
+
+        TileBody {
+            column: index as u32 % self.maze.nb_column,
+            line: index as u32 / self.maze.nb_column,
+            tile: tile.into(),
+        }
+    }
+
+    /// Hide the pheromon levels of the tiles that aren't perceived.
+    #[allow(clippy::redundant_allocation)]
+    pub(super) fn perceived_levels(
+        levels: &Arc<Box<[f32]>>,
+        perceived: Option<&[usize]>,
+    ) -> Arc<Box<[f32]>> {
+        match perceived {
+            Some(perceived) => {
+                let mut fogged = vec![0f32; levels.len()].into_boxed_slice();
+
+                for index in perceived {
+                    if let (Some(fogged), Some(level)) =
+                        (fogged.get_mut(*index), levels.get(*index))
+                    {
+                        *fogged = *level;
+                    }
+                }
+
+                Arc::new(fogged)
+            }
+            None => levels.clone(),
+        }
+    }
+}
@@ -1,12 +1,16 @@
 //! Game logic.
-use std::sync::Arc;
+use std::{cmp::Reverse, sync::Arc, time::Duration};
 
 use crate::{
+    config::PheromonConfig,
     maze::{Maze, Tile},
-    message::types::{MoveDirection, MoveMessageBody},
+    message::types::{
+        GameOverMessageBody, GameOverReason, MoveDirection, MoveMessageBody, PlayerStanding,
+        TeamStanding,
+    },
 };
 
-use super::{state::GameState, PlayerInfo};
+use super::state::{GameState, PheromonDrop, PheromonLayer, PlayerInfo};
 
 /// Update the player position.
 fn update_player_position(
@@ -92,44 +96,236 @@ impl GameState {
         let new_tile = update_player_position(&self.maze, &mut player, msg);
 
         if let Some(tile) = new_tile {
-            // The player actually moved succesfully, if it carries food, drop pheromon at his position.
-            if player.has_food {
-                self.drop_pheromon(player.position)
-            }
+            // The player actually moved succesfully, drop pheromon at his position.
+            self.drop_pheromon(player.position, player.has_food);
 
-            if player.has_food && tile.is_nest() {
+            // Food only counts when brought back to the player's own nest.
+            if player.has_food
+                && tile.is_nest()
+                && self
+                    .maze
+                    .get_nest_team(player.position.0, player.position.1)
+                    == Some(player.team)
+            {
                 player.has_food = !player.has_food;
 
-                //println!("TODO: Food put into nest");
+                player.food_delivered += 1;
+                self.food_delivered += 1;
+
+                if let Some(team_food) = self.team_food_delivered.get_mut(player.team as usize) {
+                    *team_food += 1;
+                }
             }
 
-            if tile.is_food() {
-                player.has_food = true;
+            if !player.has_food && tile.is_food() {
+                player.has_food = self.take_food(player.position);
             }
         }
 
         player
     }
 
-    /// Update the pheromon level of each tile of the maze (see [`GameState`].pheromon).
-    pub fn update_pheromon(&mut self) {
-        // sigma_ij <- (1 - evaporation) * sigma_ij
-        const EVAPORATION_RATE: f32 = 0.1;
+    /// Take food from the food source at `position`, returns `true` if there was food to take.
+    fn take_food(&mut self, position: (u32, u32)) -> bool {
+        let index = (position.0 + position.1 * self.maze.nb_column) as usize;
+
+        match self.food.get_mut(index) {
+            Some(quantity) if *quantity > 0 => {
+                // Endless food sources are never depleted.
+                if self.config.food_quantity.is_some() {
+                    *quantity -= 1;
+
+                    if *quantity == 0 {
+                        self.depleted_food.push(position);
+                    }
+                }
 
-        Arc::make_mut(&mut self.pheromon).iter_mut().for_each(|s| {
-            *s = f32::clamp(*s * (1f32 - EVAPORATION_RATE), 0f32, 1f32);
-        });
+                true
+            }
+            _ => false,
+        }
     }
 
-    /// Drop pheromon on `position`, do nothing if `position` is out of bounds.
-    pub fn drop_pheromon(&mut self, position: (u32, u32)) {
-        // Add pheromon on the tile.
-        if let Some(level) = Arc::make_mut(&mut self.pheromon)
-            .get_mut((position.0 + position.1 * self.maze.nb_column) as usize)
+    /// Check the end conditions of the game, returns the reason of the game over if one is met.
+    pub fn check_end_conditions(&self, elapsed: Duration) -> Option<GameOverReason> {
+        let conditions = &self.config.end_conditions;
+
+        if conditions
+            .time_limit
+            .is_some_and(|limit| elapsed >= Duration::from_secs(limit))
+        {
+            return Some(GameOverReason::TimeLimit);
+        }
+
+        if conditions
+            .food_goal
+            .is_some_and(|goal| self.food_delivered >= goal)
         {
-            const PHEROMON_DROP_AMOUNT: f32 = 0.2;
+            return Some(GameOverReason::FoodGoal);
+        }
+
+        // All the food sources are empty and no more food is carried, a maze without food
+        // (or whose food was never collected) never ends this way.
+        if conditions.all_food_depleted
+            && self.config.food_quantity.is_some()
+            && !self.food.is_empty()
+            && self.food_delivered > 0
+            && self.food.iter().all(|quantity| *quantity == 0)
+            && self.players.values().all(|player| !player.has_food)
+        {
+            return Some(GameOverReason::FoodDepleted);
+        }
+
+        None
+    }
+
+    /// Build the game over message of a player, containing the final standings.
+    pub fn game_over_message(
+        &self,
+        player: &PlayerInfo,
+        reason: GameOverReason,
+    ) -> GameOverMessageBody {
+        let mut players: Vec<&PlayerInfo> = self.players.values().collect();
+        players.sort_by_key(|player| Reverse(player.food_delivered));
+
+        let mut teams: Vec<(u32, u32)> = (0..)
+            .zip(self.team_food_delivered.iter().copied())
+            .collect();
+        teams.sort_by_key(|(_, food_delivered)| Reverse(*food_delivered));
+
+        // Players (or teams) with the same score share the same rank.
+        let player_rank = |score: u32| {
+            players
+                .iter()
+                .take_while(|player| player.food_delivered > score)
+                .count() as u32
+                + 1
+        };
+        let team_rank =
+            |score: u32| teams.iter().take_while(|(_, food)| *food > score).count() as u32 + 1;
+
+        GameOverMessageBody {
+            reason,
+            player_rank: player_rank(player.food_delivered),
+            player_team: player.team,
+            total_food_delivered: self.food_delivered,
+            standings: players
+                .iter()
+                .map(|player| PlayerStanding {
+                    rank: player_rank(player.food_delivered),
+                    team: player.team,
+                    food_delivered: player.food_delivered,
+                })
+                .collect(),
+            team_standings: teams
+                .iter()
+                .map(|(team, food_delivered)| TeamStanding {
+                    rank: team_rank(*food_delivered),
+                    team: *team,
+                    food_delivered: *food_delivered,
+                })
+                .collect(),
+        }
+    }
+
+    /// Update the pheromon level of each tile of the maze, for each layer (see [`GameState`].pheromon).
+    pub fn update_pheromon(&mut self) {
+        let diffusion_rate = self.config.pheromon.diffusion_rate;
+
+        for layer in self.pheromon.iter_mut() {
+            let evaporation_rate = layer.evaporation_rate;
+            let levels = Arc::make_mut(&mut layer.levels);
+
+            // sigma_ij <- (1 - evaporation) * sigma_ij
+            levels.iter_mut().for_each(|s| {
+                *s = f32::clamp(*s * (1f32 - evaporation_rate), 0f32, 1f32);
+            });
+
+            if diffusion_rate > 0f32 {
+                diffuse_pheromon(&self.maze, levels, diffusion_rate);
+            }
+        }
+    }
+
+    /// Drop pheromon on `position` for each layer whose drop rule matches the player state,
+    /// do nothing if `position` is out of bounds.
+    pub fn drop_pheromon(&mut self, position: (u32, u32), has_food: bool) {
+        let index = (position.0 + position.1 * self.maze.nb_column) as usize;
+
+        for layer in self.pheromon.iter_mut() {
+            let dropping = match layer.drop {
+                PheromonDrop::CarryingFood => has_food,
+                PheromonDrop::SearchingFood => !has_food,
+            };
+
+            if !dropping {
+                continue;
+            }
+
+            // Add pheromon on the tile.
+            if let Some(level) = Arc::make_mut(&mut layer.levels).get_mut(index) {
+                *level = (*level + layer.drop_amount).clamp(0f32, 1f32);
+            }
+        }
+    }
+}
+
+/// Spread `rate` of the pheromon of each tile evenly to its wall-free neighbouring tiles.
+fn diffuse_pheromon(maze: &Maze, levels: &mut [f32], rate: f32) {
+    // Spread from the levels before diffusion, so the result doesn't depend on the tile order.
+    let previous_levels = levels.to_vec();
+
+    for (index, level) in previous_levels.into_iter().enumerate() {
+        let position = (index as u32 % maze.nb_column, index as u32 / maze.nb_column);
+
+        let neighbours: Vec<usize> = match maze.get_tile(position.0, position.1) {
+            Some(tile) => tile
+                .get_walkable_directions()
+                .into_iter()
+                .filter_map(|direction| MoveDirection::apply_movement(direction, position))
+                .filter(|(column, line)| maze.get_tile(*column, *line).is_some())
+                .map(|(column, line)| (column + line * maze.nb_column) as usize)
+                .collect(),
+            None => continue,
+        };
 
-            *level = (*level + PHEROMON_DROP_AMOUNT).clamp(0f32, 1f32);
+        if neighbours.is_empty() {
+            continue;
+        }
+
+        let spread = level * rate;
+        levels[index] -= spread;
+
+        for neighbour in neighbours.iter() {
+            levels[*neighbour] += spread / neighbours.len() as f32;
         }
     }
+
+    levels
+        .iter_mut()
+        .for_each(|s| *s = f32::clamp(*s, 0f32, 1f32));
+}
+
+/// Create the pheromon layers of a maze of `tile_count` tiles :
+/// - `food`: dropped while carrying food, leads to the food (sent as the legacy `pheromon`)
+/// - `home`: dropped while searching for food, leads back to the nest
+pub fn default_pheromon_layers(tile_count: usize, config: &PheromonConfig) -> Vec<PheromonLayer> {
+    let levels: Arc<Box<[f32]>> = Arc::new(vec![0f32; tile_count].into_boxed_slice());
+
+    vec![
+        PheromonLayer {
+            name: "food".into(),
+            drop: PheromonDrop::CarryingFood,
+            drop_amount: config.food.drop_amount,
+            evaporation_rate: config.food.evaporation_rate,
+            levels: levels.clone(),
+        },
+        PheromonLayer {
+            name: "home".into(),
+            drop: PheromonDrop::SearchingFood,
+            drop_amount: config.home.drop_amount,
+            evaporation_rate: config.home.evaporation_rate,
+            levels,
+        },
+    ]
 }
@@ -1,16 +1,20 @@
 //! The game session.
+mod fog;
 mod logic;
 pub mod record;
+pub mod snapshot;
 pub mod state;
 
 use std::{
     collections::HashMap,
+    fs,
+    path::PathBuf,
     sync::{
         mpsc::{self, Receiver, Sender},
         Arc, Mutex,
     },
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use uuid::Uuid;
@@ -18,11 +22,15 @@ use uuid::Uuid;
 use crate::{
     error::ServerError,
     game::{
-        record::GameRecord,
-        state::{GameState, PlayerInfo},
+        record::{RecordEvent, RecordOptions},
+        snapshot::{SessionSnapshot, SnapshotOptions},
+        state::GameState,
     },
     maze::Maze,
-    message::types::{InfoMessageBody, Message},
+    message::{
+        pheromon::PheromonEncoder,
+        types::{FoodDepletedMessageBody, GameOverReason, Message, PheromonEncoding},
+    },
 };
 
 use self::record::GameRecordState;
@@ -30,15 +38,20 @@ use self::record::GameRecordState;
 /// The delay between each update all ticks.
 const UPDATE_PLAYERS_DELAY: Duration = Duration::from_secs(1);
 
-/// The delay between each pheromon update.
-const PHEROMON_UPDATE_DELAY: Duration = Duration::from_secs(5);
-
 /// The kind of message that can be sent to a game session channel.
 pub enum GameSessionMessageKind {
-    InitializePlayer(Sender<Message>),
+    /// Bind the channel of a player, who asked for a [`PheromonEncoding`].
+    /// The last field identifies the connection, see [`GameSessionMessageKind::Disconnect`].
+    InitializePlayer(Sender<Message>, PheromonEncoding, u64),
     ClientMessage(Message),
+    /// The connection with this id got closed (e.g. timed out), the player can reconnect.
+    Disconnect(u64),
     UpdateAllPlayers,
     UpdatePheromon,
+    /// Resolve the queued moves (tick-based mode).
+    Tick,
+    /// Write the session snapshot.
+    Snapshot,
 }
 
 /// A game session message sent to a game session channel.
@@ -50,9 +63,29 @@ pub struct GameSessionMessage(pub Uuid, pub GameSessionMessageKind);
 pub struct GameSessionInfo {
     pub channel: Mutex<Sender<GameSessionMessage>>,
     pub maze: Maze,
+
+    /// The players can't see the whole maze, see [`GameSessionInfo::client_maze`].
+    fog_of_war: bool,
+}
+
+impl GameSessionInfo {
+    /// Check if the game has fog of war.
+    pub fn fog_of_war(&self) -> bool {
+        self.fog_of_war
+    }
+
+    /// Get the maze to send to a joining player, fogged with the fog of war.
+    pub fn client_maze(&self) -> Maze {
+        match self.fog_of_war {
+            true => self.maze.fogged(),
+            false => self.maze.clone(),
+        }
+    }
 }
 
-struct PlayerChannel(Option<Sender<Message>>);
+/// The channel of a player, along with the encoder of its pheromon updates
+/// and the id of its connection.
+struct PlayerChannel(Option<Sender<Message>>, PheromonEncoder, u64);
 
 /// A game session.
 /// This instance should be only used by a single thread.
@@ -65,50 +98,78 @@ pub struct GameSession {
 
     state: GameState,
     record_state: Option<GameRecordState>,
+    snapshot: Option<SnapshotOptions>,
 
     /// Internal instance UUID, used for debugging.
     uuid: Uuid,
+
+    /// When the game session has been created, used for the time limit.
+    start_instant: Instant,
 }
 
 /// Try sending a [`Message`] to the [`PlayerChannel`] (if a channel is bound to it).
-/// Otherwise, invalidates the channel.
+/// Otherwise, invalidates the channel and records the disconnection.
 fn try_sending_to_channel(
     channel: &mut PlayerChannel,
-    message: Message,
+    mut message: Message,
     uuid: &Uuid,
     session_uuid: &Uuid,
+    record_state: &mut Option<GameRecordState>,
 ) {
     // If the player has an active channel.
     if let Some(sender) = &channel.0 {
+        if let Message::Info(info) = &mut message {
+            channel.1.encode(info);
+        }
+
         // Try sending a info message.
         if sender.send(message).is_err() {
             // We can't send message to channel, invalidate the channel.
             eprintln!("{}: player {uuid} disconnected", session_uuid.as_braced());
 
             channel.0.take();
+
+            if let Some(record_state) = record_state {
+                record_state.track(uuid, RecordEvent::Disconnect);
+            }
         }
     }
 }
 
 impl GameSession {
     /// Creates a new [`GameSession`].
-    pub fn new(state: GameState, recorded: bool) -> (Self, Arc<GameSessionInfo>) {
+    pub fn new(
+        state: GameState,
+        record: Option<RecordOptions>,
+        snapshot: Option<SnapshotOptions>,
+    ) -> (Self, Arc<GameSessionInfo>) {
+        // Create the record state the game if needed.
+        let record_state =
+            record.map(|options| GameRecordState::new(state.maze.clone(), state.config, options));
+
+        Self::with_record_state(state, record_state, snapshot)
+    }
+
+    /// Create a new [`GameSession`], recording into `record_state` (if any).
+    fn with_record_state(
+        state: GameState,
+        record_state: Option<GameRecordState>,
+        snapshot: Option<SnapshotOptions>,
+    ) -> (Self, Arc<GameSessionInfo>) {
         let (sender, receiver) = mpsc::channel::<GameSessionMessage>();
         let info = Arc::new(GameSessionInfo {
             channel: sender.into(),
             maze: state.maze.clone(),
+            fog_of_war: state.config.fog_of_war.is_some(),
         });
 
         // Build player channels info using state.players (assume not connected).
         let players: HashMap<Uuid, PlayerChannel> = state
             .players
             .iter()
-            .map(|(uuid, _)| (*uuid, PlayerChannel(None)))
+            .map(|(uuid, _)| (*uuid, PlayerChannel(None, PheromonEncoder::default(), 0)))
             .collect();
 
-        // Create the record state the game if needed.
-        let record_state = recorded.then(|| GameRecordState::new(state.maze.clone()));
-
         (
             Self {
                 players,
@@ -117,6 +178,8 @@ impl GameSession {
                 channel: receiver,
                 _info: info.clone(),
                 record_state,
+                snapshot,
+                start_instant: Instant::now(),
             },
             info,
         )
@@ -124,31 +187,52 @@ impl GameSession {
 
     /// Process a client message.
     fn process_player_message(&mut self, uuid: &Uuid, message: &Message) {
-        let (players, state) = (&mut self.players, &mut self.state);
+        let (players, state, record_state) =
+            (&mut self.players, &mut self.state, &mut self.record_state);
 
         let channel = players
             .get_mut(uuid)
             .expect("Player must exist to be able to send message");
 
+        // Answer the keep-alive pings, they aren't part of the game.
+        if let Message::Ping(body) = message {
+            try_sending_to_channel(
+                channel,
+                Message::Pong(body.clone()),
+                uuid,
+                &self.uuid,
+                record_state,
+            );
+
+            return;
+        }
+
         // Track the message if we are recording.
-        if let Some(state) = &mut self.record_state {
-            state.track(uuid, message);
+        if let Some(record_state) = record_state {
+            record_state.track(
+                uuid,
+                RecordEvent::ClientMessage {
+                    message: message.clone(),
+                },
+            );
         }
 
-        match state.process_message(uuid, message) {
-            Ok(info) => {
+        // In tick-based mode, the moves are resolved and the players informed on the next tick.
+        let result = match state.config.tick {
+            Some(_) => state.queue_message(uuid, message),
+            None => state.process_message(uuid, message).map(|info| {
                 try_sending_to_channel(
                     channel,
-                    Message::Info(InfoMessageBody {
-                        player_column: info.position.0,
-                        player_line: info.position.1,
-                        player_has_food: info.has_food,
-                        pheromon: self.state.pheromon.clone(),
-                    }),
+                    Message::Info(state.info_message(&info)),
                     uuid,
                     &self.uuid,
+                    record_state,
                 );
-            }
+            }),
+        };
+
+        match result {
+            Ok(()) => (),
 
             // Inform the player of an unexpected message.
             Err(ServerError::UnexpectedParameter) => {
@@ -160,6 +244,7 @@ impl GameSession {
                     },
                     uuid,
                     &self.uuid,
+                    record_state,
                 );
             }
             Err(e) => {
@@ -168,14 +253,63 @@ impl GameSession {
         }
     }
 
+    /// Track a [`RecordEvent`] if we are recording, `uuid` is nil for the session events.
+    fn track(&mut self, uuid: &Uuid, event: RecordEvent) {
+        if let Some(record_state) = &mut self.record_state {
+            record_state.track(uuid, event);
+        }
+    }
+
+    /// Save a checkpoint of the game state if we are recording.
+    fn checkpoint(&mut self) {
+        if let Some(record_state) = &mut self.record_state {
+            record_state.checkpoint(&self.state);
+        }
+    }
+
+    /// Send a [`Message`] to all the players that revealed the tile at `position`
+    /// (all the players without fog of war).
+    fn broadcast_revealed(&mut self, message: &Message, position: (u32, u32)) {
+        self.players
+            .iter_mut()
+            .filter(|(uuid, _)| self.state.is_revealed(uuid, position))
+            .for_each(|(uuid, channel)| {
+                try_sending_to_channel(
+                    channel,
+                    message.clone(),
+                    uuid,
+                    &self.uuid,
+                    &mut self.record_state,
+                );
+            });
+    }
+
+    /// Inform the players of the food sources that just got depleted.
+    fn notify_depleted_food(&mut self) {
+        for (column, line) in self.state.take_depleted_food() {
+            self.broadcast_revealed(
+                &Message::FoodDepleted(FoodDepletedMessageBody { column, line }),
+                (column, line),
+            );
+        }
+    }
+
     /**
     Initialize the player [`Uuid`] using the provided [`Sender<Message>`].
 
     If the player already exists in the session (e.g was previously connected), reset its channel using `sender`.
 
-    Otherwise, set player at initial nest coordinates.
+    Otherwise, put the player in a team and set it at its nest coordinates.
+
+    The pheromon are then sent using `encoding`, starting with the whole grids.
     */
-    fn init_player(&mut self, uuid: &Uuid, sender: Sender<Message>) -> Result<(), ServerError> {
+    fn init_player(
+        &mut self,
+        uuid: &Uuid,
+        sender: Sender<Message>,
+        encoding: PheromonEncoding,
+        connection: u64,
+    ) -> Result<(), ServerError> {
         // Check if the player exists in the session.
         match self.players.get_mut(uuid) {
             Some(channel) => {
@@ -190,6 +324,10 @@ impl GameSession {
 
                         // Rebind the player channel using sender.
                         let _ = channel.0.replace(sender);
+                        channel.1 = PheromonEncoder::new(encoding);
+                        channel.2 = connection;
+
+                        self.track(uuid, RecordEvent::Reconnect);
 
                         Ok(())
                     }
@@ -199,51 +337,116 @@ impl GameSession {
                 // Initialize the player info using the session maze, then add this player to the session.
                 println!("{}: {} connected", self.uuid.as_braced(), uuid);
 
-                let _ = self.players.insert(*uuid, PlayerChannel(Some(sender)));
+                let _ = self.players.insert(
+                    *uuid,
+                    PlayerChannel(Some(sender), PheromonEncoder::new(encoding), connection),
+                );
+
+                let _ = self.state.add_player(*uuid);
 
-                let _ = self
-                    .state
-                    .players
-                    .insert(*uuid, PlayerInfo::new(&self.state.maze));
+                self.track(uuid, RecordEvent::Join);
 
                 Ok(())
             }
         }
     }
 
-    /// Run the game session loop.
-    pub fn run(&mut self) -> Result<(), ServerError> {
-        let sender = self._info.channel.lock().unwrap().clone();
+    /// Unbind the channel of a player if it's still bound to `connection`, so that it can
+    /// reconnect. A rejected connection (see [`ServerError::AlreadyConnected`]) unbinds nothing.
+    fn disconnect_player(&mut self, uuid: &Uuid, connection: u64) {
+        if let Some(channel) = self.players.get_mut(uuid) {
+            if channel.0.is_some() && channel.2 == connection {
+                println!("{}: {uuid} disconnected", self.uuid.as_braced());
 
-        // Create the update all notifier thread
-        let player_updater_channel = sender.clone();
-        thread::Builder::new()
-            .name(format!("GameSession updater {}", self.uuid))
-            .spawn(move || -> Result<(), ServerError> {
-                loop {
-                    thread::sleep(UPDATE_PLAYERS_DELAY);
-                    player_updater_channel.send(GameSessionMessage(
-                        uuid::Uuid::default(),
-                        GameSessionMessageKind::UpdateAllPlayers,
-                    ))?;
-                }
-            })
-            .unwrap();
+                channel.0.take();
+
+                self.track(uuid, RecordEvent::Disconnect);
+            }
+        }
+    }
+
+    /// Send the game view of each player, returns `false` if there is no active player left.
+    fn update_all_players(&mut self) -> bool {
+        // NOTE: We may need to invalidate the player channel if a send fails.
+
+        if self.players.iter().all(|(_, channel)| channel.0.is_none()) {
+            return false;
+        }
+
+        self.players.iter_mut().for_each(|(uuid, channel)| {
+            if let Some(info) = self.state.players.get(uuid) {
+                try_sending_to_channel(
+                    channel,
+                    Message::Info(self.state.info_message(info)),
+                    uuid,
+                    &self.uuid,
+                    &mut self.record_state,
+                );
+            }
+        });
+
+        true
+    }
+
+    /// Time elapsed since the start of the game, counted in ticks in tick-based mode
+    /// to be independent of the wall-clock.
+    fn elapsed(&self) -> Duration {
+        match self.state.config.tick {
+            Some(tick) => Duration::from_millis(self.state.tick * tick.tick_duration),
+            None => self.start_instant.elapsed(),
+        }
+    }
+
+    /// Spawn a thread sending `kind` to the session every `delay`.
+    fn spawn_notifier(
+        &self,
+        name: &str,
+        delay: Duration,
+        kind: fn() -> GameSessionMessageKind,
+    ) -> Result<(), ServerError> {
+        let channel = self._info.channel.lock()?.clone();
 
-        // Create the pheromon update notifier thread
-        let pheromon_updater_channel = sender;
         thread::Builder::new()
-            .name(format!("Pheromon updater {}", self.uuid))
+            .name(format!("{name} {}", self.uuid))
             .spawn(move || -> Result<(), ServerError> {
                 loop {
-                    thread::sleep(PHEROMON_UPDATE_DELAY);
-                    pheromon_updater_channel.send(GameSessionMessage(
-                        uuid::Uuid::default(),
-                        GameSessionMessageKind::UpdatePheromon,
-                    ))?;
+                    thread::sleep(delay);
+                    channel.send(GameSessionMessage(uuid::Uuid::default(), kind()))?;
                 }
-            })
-            .unwrap();
+            })?;
+
+        Ok(())
+    }
+
+    /// Run the game session loop.
+    pub fn run(&mut self) -> Result<(), ServerError> {
+        match self.state.config.tick {
+            // A single tick clock drives the players updates and the pheromon updates.
+            Some(tick) => self.spawn_notifier(
+                "Tick notifier",
+                Duration::from_millis(tick.tick_duration),
+                || GameSessionMessageKind::Tick,
+            )?,
+            None => {
+                // Create the update all notifier thread
+                self.spawn_notifier("GameSession updater", UPDATE_PLAYERS_DELAY, || {
+                    GameSessionMessageKind::UpdateAllPlayers
+                })?;
+
+                // Create the pheromon update notifier thread, the delay is set by the game rules.
+                self.spawn_notifier(
+                    "Pheromon updater",
+                    Duration::from_millis(self.state.config.pheromon.update_delay),
+                    || GameSessionMessageKind::UpdatePheromon,
+                )?;
+            }
+        }
+
+        if let Some(snapshot) = &self.snapshot {
+            self.spawn_notifier("Snapshot notifier", snapshot.delay, || {
+                GameSessionMessageKind::Snapshot
+            })?;
+        }
 
         self.run_loop()
     }
@@ -255,45 +458,150 @@ impl GameSession {
 
             match kind {
                 GameSessionMessageKind::ClientMessage(message) => {
-                    self.process_player_message(&uuid, &message)
+                    self.process_player_message(&uuid, &message);
+                    self.notify_depleted_food();
                 }
-                GameSessionMessageKind::InitializePlayer(sender) => {
-                    if let Err(e) = self.init_player(&uuid, sender.clone()) {
-                        // Notify the player of a failure.
-                        sender.send(Message::Error(e)).ok();
+                GameSessionMessageKind::InitializePlayer(sender, encoding, connection) => {
+                    match self.init_player(&uuid, sender.clone(), encoding, connection) {
+                        Ok(()) => {
+                            // Inform the player of the food sources depleted before it joined.
+                            self.state
+                                .get_depleted_food()
+                                .filter(|position| self.state.is_revealed(&uuid, *position))
+                                .for_each(|(column, line)| {
+                                    sender
+                                        .send(Message::FoodDepleted(FoodDepletedMessageBody {
+                                            column,
+                                            line,
+                                        }))
+                                        .ok();
+                                });
+
+                            // Give the player back its knowledge of the maze.
+                            if let (Some(_), Some(info), Some(channel)) = (
+                                self.state.config.fog_of_war,
+                                self.state.players.get(&uuid),
+                                self.players.get_mut(&uuid),
+                            ) {
+                                let mut message = self.state.info_message(info);
+                                message.tiles = self.state.revealed_tiles(&uuid);
+
+                                try_sending_to_channel(
+                                    channel,
+                                    Message::Info(message),
+                                    &uuid,
+                                    &self.uuid,
+                                    &mut self.record_state,
+                                );
+                            }
+                        }
+                        Err(e) => {
+                            // Notify the player of a failure.
+                            sender.send(Message::Error(e)).ok();
+                        }
                     }
                 }
+                GameSessionMessageKind::Disconnect(connection) => {
+                    self.disconnect_player(&uuid, connection)
+                }
                 GameSessionMessageKind::UpdateAllPlayers => {
-                    // NOTE: We may need to invalidate the player channel if a send fails.
+                    self.track(&uuid, RecordEvent::UpdateAllPlayers);
+                    self.checkpoint();
 
-                    //TODO: Consider another way to end the game.
-                    if self.players.iter().all(|(_, channel)| channel.0.is_none()) {
+                    if !self.update_all_players() {
                         println!("{}: No active player, stopping", self.uuid.as_braced());
 
-                        if let Some(state) = &self.record_state {
-                            println!("Record :\n{:#?}", GameRecord::from(state.clone()));
-                        }
-
+                        self.finish();
                         return Ok(());
                     }
+                }
+                GameSessionMessageKind::UpdatePheromon => {
+                    self.track(&uuid, RecordEvent::UpdatePheromon);
 
-                    self.players.iter_mut().for_each(|(uuid, channel)| {
-                        if let Some(info) = self.state.players.get(uuid) {
-                            try_sending_to_channel(
-                                channel,
-                                Message::Info(InfoMessageBody {
-                                    player_column: info.position.0,
-                                    player_line: info.position.1,
-                                    player_has_food: info.has_food,
-                                    pheromon: self.state.pheromon.clone(),
-                                }),
-                                uuid,
-                                &self.uuid,
-                            );
-                        }
-                    })
+                    self.state.update_pheromon();
+                    self.checkpoint();
+                }
+                GameSessionMessageKind::Snapshot => self.save_snapshot(),
+                GameSessionMessageKind::Tick => {
+                    self.track(&uuid, RecordEvent::Tick);
+
+                    self.state.step_tick();
+                    self.checkpoint();
+                    self.notify_depleted_food();
+
+                    if !self.update_all_players() {
+                        println!("{}: No active player, stopping", self.uuid.as_braced());
+
+                        self.finish();
+                        return Ok(());
+                    }
                 }
-                GameSessionMessageKind::UpdatePheromon => self.state.update_pheromon(),
+            }
+
+            if let Some(reason) = self.state.check_end_conditions(self.elapsed()) {
+                println!("{}: Game over ({reason:?})", self.uuid.as_braced());
+
+                self.game_over(reason);
+                self.finish();
+                return Ok(());
+            }
+        }
+    }
+
+    /// Send the final standings to all the players.
+    fn game_over(&mut self, reason: GameOverReason) {
+        self.players.iter_mut().for_each(|(uuid, channel)| {
+            if let Some(info) = self.state.players.get(uuid) {
+                try_sending_to_channel(
+                    channel,
+                    Message::GameOver(self.state.game_over_message(info, reason)),
+                    uuid,
+                    &self.uuid,
+                    &mut self.record_state,
+                );
+            }
+        });
+    }
+
+    /// Write the session snapshot, if snapshots are enabled.
+    fn save_snapshot(&self) {
+        if let Some(options) = &self.snapshot {
+            if let Err(err) = options.save(
+                &self.uuid,
+                self.start_instant.elapsed(),
+                &self.state,
+                self.record_state.as_ref(),
+            ) {
+                eprintln!(
+                    "{}: unable to write the snapshot : {err}",
+                    self.uuid.as_braced()
+                );
+            }
+        }
+    }
+
+    /// Finalize the game session before stopping it.
+    fn finish(&mut self) {
+        // The game is over, there is nothing to restore anymore.
+        if let Some(options) = &self.snapshot {
+            let _ = fs::remove_file(SessionSnapshot::path(&options.directory, &self.uuid));
+        }
+
+        if let Some(mut record_state) = self.record_state.take() {
+            record_state.set_scores(&self.state);
+
+            let directory = record_state.options.directory.clone();
+
+            match record_state.into_record(self.uuid).save(&directory) {
+                Ok(path) => println!(
+                    "{}: record written to {}",
+                    self.uuid.as_braced(),
+                    path.display()
+                ),
+                Err(err) => eprintln!(
+                    "{}: unable to write the record : {err}",
+                    self.uuid.as_braced()
+                ),
             }
         }
     }
@@ -301,20 +609,67 @@ impl GameSession {
     /// Start in a new thread the game session loop.
     pub fn start_new(
         state: GameState,
-        recorded: bool,
+        record: Option<RecordOptions>,
+        snapshot: Option<SnapshotOptions>,
+    ) -> Result<Arc<GameSessionInfo>, ServerError> {
+        let record_state =
+            record.map(|options| GameRecordState::new(state.maze.clone(), state.config, options));
+
+        Self::spawn(
+            Uuid::new_v4(),
+            state,
+            record_state,
+            snapshot,
+            Duration::ZERO,
+        )
+    }
+
+    /// Restore the game session `session` from its snapshot and start its loop in a new thread,
+    /// the players (AI included) have to reconnect using their UUID.
+    /// A recorded game keeps being recorded, its record holds the events before the restore.
+    pub fn restore(
+        session: Uuid,
+        snapshot: SessionSnapshot,
+        directory: PathBuf,
+        delay: Duration,
+    ) -> Result<Arc<GameSessionInfo>, ServerError> {
+        let options = SnapshotOptions {
+            directory,
+            delay,
+            difficulty: snapshot.difficulty,
+            room: snapshot.room,
+            ai_players: snapshot.ai_players,
+        };
+
+        Self::spawn(
+            session,
+            snapshot.state,
+            snapshot.record,
+            Some(options),
+            snapshot.elapsed,
+        )
+    }
+
+    fn spawn(
+        session_uuid: Uuid,
+        state: GameState,
+        record_state: Option<GameRecordState>,
+        snapshot: Option<SnapshotOptions>,
+        elapsed: Duration,
     ) -> Result<Arc<GameSessionInfo>, ServerError> {
         // TODO: Maybe make it asynchronous using lobby's channel ?
 
         // Send GameSessionInfo through a channel.
         let (sender, reader) = mpsc::sync_channel::<Arc<GameSessionInfo>>(0);
 
-        let session_uuid = Uuid::new_v4();
-
         let _ = thread::Builder::new()
             .name(format!("Game Instance {}", session_uuid.as_braced()))
             .spawn(move || {
-                let (mut session, info) = Self::new(state, recorded);
+                let (mut session, info) = Self::with_record_state(state, record_state, snapshot);
                 session.uuid = session_uuid;
+                session.start_instant = Instant::now()
+                    .checked_sub(elapsed)
+                    .unwrap_or_else(Instant::now);
 
                 sender.send(info).unwrap();
 
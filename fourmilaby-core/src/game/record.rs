@@ -1,80 +1,379 @@
 //! Recording system.
+//!
+//! # Record file format
+//!
+//! A record is a JSON lines file (`<session UUID>.jsonl`) :
+//! - the first line is the [`RecordHeader`] : the format version ([`RECORD_FORMAT_VERSION`]),
+//!   the [`RecordMetadata`], the maze, the players and their final scores.
+//! - each following line is an [`EventRecord`], in the order the game session handled them.
+//!   `delay` is the time elapsed since the previous event (`{"secs": u64, "nanos": u32}`).
+//!   The periodic events may hold a `checkpoint`, the [`StateChecksum`] of the game state
+//!   right after the event, used to verify replays.
+//!
+//! The version is bumped on every incompatible change of the format.
+//!
+//! ## Versions
+//! - 1 : only the client messages are recorded (`{"delay", "player", "message"}` lines).
+//! - 2 : the whole session timeline is recorded (joins, reconnections, disconnections,
+//!   pheromon updates, ticks, ...), along with the seed of the maze.
 use std::{
+    cmp::Reverse,
     collections::HashSet,
-    time::{Duration, Instant},
+    fs::{self, File},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
 };
 
-use crate::{maze::Maze, message::types::Message};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{GameConfig, GeneratorConfig},
+    error::ServerError,
+    maze::Maze,
+    message::types::Message,
+};
+
+use super::state::{GameState, StateChecksum};
 
 use uuid::Uuid;
 
-/// A message from a recorded game.
-#[derive(Clone, Debug)]
-pub struct MessageRecord {
+/// Version of the record file format, see the module documentation.
+pub const RECORD_FORMAT_VERSION: u32 = 2;
+
+/// Where and how a game session records its game.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordOptions {
+    /// Directory where the record is written at the end of the game.
+    pub directory: PathBuf,
+
+    /// Generator configuration the maze was generated with, kept as metadata.
+    pub generator: GeneratorConfig,
+
+    /// Seed of the random generator the maze was generated with.
+    pub seed: Option<u64>,
+}
+
+/// Information about a recorded game.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordMetadata {
+    /// UUID of the recorded game session.
+    pub session: Uuid,
+
+    /// When the game session started, in milliseconds since the Unix epoch.
+    pub start_time: u64,
+
+    pub generator: GeneratorConfig,
+    pub game: GameConfig,
+
+    /// Seed of the random generator the maze was generated with (since version 2).
+    #[serde(default)]
+    pub seed: Option<u64>,
+}
+
+/// First line of a record file.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordHeader {
+    pub version: u32,
+    pub metadata: RecordMetadata,
+    pub maze: Maze,
+    pub players: Box<[Uuid]>,
+    pub scores: Box<[PlayerScore]>,
+}
+
+/// An event of a recorded game session, mirrors the [`super::GameSessionMessageKind`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum RecordEvent {
+    /// The player joined the game.
+    Join,
+    /// The player reconnected to the game.
+    Reconnect,
+    /// The player got disconnected (its channel got invalidated).
+    Disconnect,
+    /// The player sent a message.
+    ClientMessage {
+        message: Message,
+    },
+    UpdateAllPlayers,
+    UpdatePheromon,
+    Tick,
+}
+
+/// An event from a recorded game, `player` is nil for the events that don't concern a player.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EventRecord {
     pub delay: Duration,
     pub player: Uuid,
-    pub message: Message,
+    pub event: RecordEvent,
+
+    /// Checksum of the game state right after the event.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checkpoint: Option<StateChecksum>,
+}
+
+/// A message from a version 1 record.
+#[derive(Deserialize)]
+struct LegacyMessageRecord {
+    delay: Duration,
+    player: Uuid,
+    message: Message,
+}
+
+/// The final score of a player in a recorded game.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlayerScore {
+    pub player: Uuid,
+    pub team: u32,
+    pub food_delivered: u32,
 }
 
 /// A recorded game.
 #[derive(Debug)]
 pub struct GameRecord {
-    pub messages: Box<[MessageRecord]>,
+    pub events: Box<[EventRecord]>,
     pub maze: Maze,
     pub players: Box<[Uuid]>,
+
+    /// Player scores, from the best player to the worst one.
+    pub scores: Box<[PlayerScore]>,
+
+    pub metadata: RecordMetadata,
 }
 
-/// Freeze a [`GameRecordState`] into a [`GameRecord`].
-impl From<GameRecordState> for GameRecord {
-    fn from(state: GameRecordState) -> Self {
-        GameRecord {
-            messages: state.messages.into_boxed_slice(),
-            maze: state.maze,
-            players: state.players.into_iter().collect(),
+impl GameRecord {
+    /// Write the record in `directory` (created if needed), returns the path of the record file.
+    pub fn save(&self, directory: &Path) -> Result<PathBuf, ServerError> {
+        fs::create_dir_all(directory)?;
+
+        let path = directory.join(format!("{}.jsonl", self.metadata.session));
+        let mut writer = BufWriter::new(File::create(&path)?);
+
+        let header = RecordHeader {
+            version: RECORD_FORMAT_VERSION,
+            metadata: self.metadata.clone(),
+            maze: self.maze.clone(),
+            players: self.players.clone(),
+            scores: self.scores.clone(),
+        };
+
+        serde_json::to_writer(&mut writer, &header)?;
+        writeln!(writer)?;
+
+        for event in self.events.iter() {
+            serde_json::to_writer(&mut writer, event)?;
+            writeln!(writer)?;
+        }
+
+        writer.flush()?;
+
+        Ok(path)
+    }
+
+    /// Load a record written by [`GameRecord::save`].
+    pub fn load(path: &Path) -> Result<Self, ServerError> {
+        let mut lines = BufReader::new(File::open(path)?).lines();
+
+        let header: RecordHeader = match lines.next() {
+            Some(line) => serde_json::from_str(&line?)?,
+            None => return Err(ServerError::Other("Empty record file".into())),
+        };
+
+        let lines = lines.filter(|line| !line.as_ref().is_ok_and(|line| line.trim().is_empty()));
+
+        let events = match header.version {
+            RECORD_FORMAT_VERSION => lines
+                .map(|line| Ok(serde_json::from_str::<EventRecord>(&line?)?))
+                .collect::<Result<Box<[EventRecord]>, ServerError>>()?,
+            1 => {
+                let messages = lines
+                    .map(|line| Ok(serde_json::from_str::<LegacyMessageRecord>(&line?)?))
+                    .collect::<Result<Vec<LegacyMessageRecord>, ServerError>>()?;
+
+                legacy_timeline(messages, &header.metadata.game)
+            }
+            version => {
+                return Err(ServerError::Other(
+                    format!("Unsupported record format version {version}").into(),
+                ))
+            }
+        };
+
+        Ok(GameRecord {
+            events,
+            maze: header.maze,
+            players: header.players,
+            scores: header.scores,
+            metadata: header.metadata,
+        })
+    }
+}
+
+/// Rebuild an approximate timeline from the messages of a version 1 record :
+/// players join on their first message, and the periodic updates happen on time.
+fn legacy_timeline(messages: Vec<LegacyMessageRecord>, game: &GameConfig) -> Box<[EventRecord]> {
+    let (update_delay, update_event) = match game.tick {
+        Some(tick) => (tick.tick_duration, RecordEvent::Tick),
+        None => (game.pheromon.update_delay, RecordEvent::UpdatePheromon),
+    };
+    let update_delay = Duration::from_millis(update_delay.max(1));
+
+    let mut events = Vec::with_capacity(messages.len());
+    let mut players = HashSet::new();
+
+    // Time since the start of the game, of the last event and of the last periodic update.
+    let (mut time, mut last_event, mut last_update) =
+        (Duration::ZERO, Duration::ZERO, Duration::ZERO);
+
+    for record in messages {
+        time += record.delay;
+
+        while last_update + update_delay <= time {
+            last_update += update_delay;
+
+            events.push(EventRecord {
+                delay: last_update - last_event,
+                player: Uuid::nil(),
+                event: update_event.clone(),
+                checkpoint: None,
+            });
+            last_event = last_update;
+        }
+
+        if players.insert(record.player) {
+            events.push(EventRecord {
+                delay: time - last_event,
+                player: record.player,
+                event: RecordEvent::Join,
+                checkpoint: None,
+            });
+            last_event = time;
         }
+
+        events.push(EventRecord {
+            delay: time - last_event,
+            player: record.player,
+            event: RecordEvent::ClientMessage {
+                message: record.message,
+            },
+            checkpoint: None,
+        });
+        last_event = time;
     }
+
+    events.into_boxed_slice()
 }
 
 /// The recording state for an active game.
-/// Used to iteratively record a game.
-#[derive(Clone)]
+/// Used to iteratively record a game, and kept in the session snapshots.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub(super) struct GameRecordState {
     pub maze: Maze,
     pub players: HashSet<Uuid>,
-    pub messages: Vec<MessageRecord>,
-    pub last_message_instant: Option<Instant>,
+    pub events: Vec<EventRecord>,
+
+    /// Not kept in the snapshots, the first event after a restore has no delay.
+    #[serde(skip)]
+    pub last_event_instant: Option<Instant>,
+
+    /// Only set at the end of the game.
+    #[serde(skip)]
+    pub scores: Vec<PlayerScore>,
+    pub options: RecordOptions,
+    pub start_time: SystemTime,
+    pub game: GameConfig,
 }
 
 impl GameRecordState {
     /// Create a new empty [`GameRecordState`].
-    pub fn new(maze: Maze) -> Self {
+    pub fn new(maze: Maze, game: GameConfig, options: RecordOptions) -> Self {
         Self {
-            messages: vec![],
+            events: vec![],
             players: HashSet::new(),
-            last_message_instant: None,
+            last_event_instant: None,
+            scores: vec![],
             maze,
+            options,
+            start_time: SystemTime::now(),
+            game,
+        }
+    }
+
+    /// Freeze the [`GameRecordState`] of the session `session` into a [`GameRecord`].
+    pub fn into_record(mut self, session: Uuid) -> GameRecord {
+        self.scores
+            .sort_by_key(|score| Reverse(score.food_delivered));
+
+        let start_time = self
+            .start_time
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+
+        GameRecord {
+            events: self.events.into_boxed_slice(),
+            maze: self.maze,
+            players: self.players.into_iter().collect(),
+            scores: self.scores.into_boxed_slice(),
+            metadata: RecordMetadata {
+                session,
+                start_time,
+                generator: self.options.generator,
+                game: self.game,
+                seed: self.options.seed,
+            },
         }
     }
 
-    /// Add a [`Message`] to the [`GameRecordState`].
-    pub fn track(&mut self, player: &Uuid, message: &Message) {
+    /// Take the scores of the players from the [`GameState`].
+    pub fn set_scores(&mut self, state: &GameState) {
+        self.scores = state
+            .players
+            .iter()
+            .map(|(uuid, info)| PlayerScore {
+                player: *uuid,
+                team: info.team,
+                food_delivered: info.food_delivered,
+            })
+            .collect();
+    }
+
+    /// Add a [`RecordEvent`] of the player to the [`GameRecordState`],
+    /// `player` is nil for the events that don't concern a player.
+    pub fn track(&mut self, player: &Uuid, event: RecordEvent) {
         // Add the player to the set if it doesn't exist.
-        self.players.insert(*player);
+        if matches!(event, RecordEvent::Join) {
+            self.players.insert(*player);
+        }
 
-        // Compute the time between the last message and now.
+        // Compute the time between the last event and now.
         let now = Instant::now();
 
-        let delay = match self.last_message_instant {
+        let delay = match self.last_event_instant {
             Some(instant) => now.duration_since(instant),
             None => Duration::ZERO,
         };
 
-        self.last_message_instant = Some(now);
+        self.last_event_instant = Some(now);
 
-        self.messages.push(MessageRecord {
+        self.events.push(EventRecord {
             delay,
             player: *player,
-            message: message.clone(),
+            event,
+            checkpoint: None,
         });
     }
+
+    /// Save the checksum of the [`GameState`] as a checkpoint of the last tracked event.
+    pub fn checkpoint(&mut self, state: &GameState) {
+        if let Some(event) = self.events.last_mut() {
+            event.checkpoint = Some(state.checksum());
+        }
+    }
 }
@@ -0,0 +1,152 @@
+//! Game session snapshots, used to restore the running games after a server restart.
+//!
+//! Each running session periodically writes its snapshot (`<session UUID>.json`)
+//! in the snapshot directory, the snapshot is removed once the session is over.
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::error::ServerError;
+
+use super::{record::GameRecordState, state::GameState};
+
+/// Version of the snapshot file format, bumped on every incompatible change.
+pub const SNAPSHOT_FORMAT_VERSION: u32 = 1;
+
+/// Where and how often a game session writes its snapshots,
+/// along with the lobby information needed to restore it.
+#[derive(Clone, Debug)]
+pub struct SnapshotOptions {
+    pub directory: PathBuf,
+    pub delay: Duration,
+
+    pub difficulty: u32,
+    pub room: Option<Box<str>>,
+
+    /// Players controlled by the server AI.
+    pub ai_players: Box<[Uuid]>,
+}
+
+/// A snapshot of a running game session.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionSnapshot {
+    pub version: u32,
+
+    pub difficulty: u32,
+    pub room: Option<Box<str>>,
+    pub ai_players: Box<[Uuid]>,
+
+    /// Time elapsed since the start of the game.
+    pub elapsed: Duration,
+    pub state: GameState,
+
+    /// The game recorded so far, if the game is recorded.
+    #[serde(default)]
+    pub(super) record: Option<GameRecordState>,
+}
+
+/// Borrowed [`SessionSnapshot`], serialized the same way.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SessionSnapshotRef<'a> {
+    version: u32,
+    difficulty: u32,
+    room: &'a Option<Box<str>>,
+    ai_players: &'a [Uuid],
+    elapsed: Duration,
+    state: &'a GameState,
+    record: Option<&'a GameRecordState>,
+}
+
+impl SnapshotOptions {
+    /// Write the snapshot of the session `session` in the snapshot directory (created if needed).
+    pub(super) fn save(
+        &self,
+        session: &Uuid,
+        elapsed: Duration,
+        state: &GameState,
+        record: Option<&GameRecordState>,
+    ) -> Result<(), ServerError> {
+        fs::create_dir_all(&self.directory)?;
+
+        let snapshot = SessionSnapshotRef {
+            version: SNAPSHOT_FORMAT_VERSION,
+            difficulty: self.difficulty,
+            room: &self.room,
+            ai_players: &self.ai_players,
+            elapsed,
+            state,
+            record,
+        };
+
+        // Write then rename, a crash while writing can't corrupt the previous snapshot.
+        let path = SessionSnapshot::path(&self.directory, session);
+        let temporary_path = path.with_extension("json.tmp");
+
+        fs::write(&temporary_path, serde_json::to_vec(&snapshot)?)?;
+        fs::rename(&temporary_path, &path)?;
+
+        Ok(())
+    }
+}
+
+impl SessionSnapshot {
+    /// Path of the snapshot of the session `session` in `directory`.
+    pub fn path(directory: &Path, session: &Uuid) -> PathBuf {
+        directory.join(format!("{session}.json"))
+    }
+
+    /// Load a snapshot written by [`SnapshotOptions::save`].
+    pub fn load(path: &Path) -> Result<Self, ServerError> {
+        let snapshot: Self = serde_json::from_slice(&fs::read(path)?)?;
+
+        if snapshot.version != SNAPSHOT_FORMAT_VERSION {
+            return Err(ServerError::Other(
+                format!("Unsupported snapshot format version {}", snapshot.version).into(),
+            ));
+        }
+
+        Ok(snapshot)
+    }
+
+    /// Load all the snapshots of `directory` along with the UUID of their session,
+    /// the invalid ones are skipped.
+    pub fn load_all(directory: &Path) -> Vec<(Uuid, Self)> {
+        let entries = match fs::read_dir(directory) {
+            Ok(entries) => entries,
+            Err(_) => return vec![],
+        };
+
+        entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension == "json")
+            })
+            .filter_map(|path| {
+                // The snapshots are named after their session.
+                let Some(session) = path
+                    .file_stem()
+                    .and_then(|stem| Uuid::parse_str(&stem.to_string_lossy()).ok())
+                else {
+                    eprintln!("Not a snapshot : {}", path.display());
+                    return None;
+                };
+
+                match Self::load(&path) {
+                    Ok(snapshot) => Some((session, snapshot)),
+                    Err(err) => {
+                        eprintln!("Unable to load snapshot {} : {err}", path.display());
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+}
@@ -1,54 +1,304 @@
 //! The game state than can be serialized.
 use std::{collections::HashMap, sync::Arc};
 
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::{error::ServerError, maze::Maze, message::types::Message};
+use crate::{
+    config::{AntVisibility, GameConfig},
+    error::ServerError,
+    maze::{Maze, Tile},
+    message::types::{AntBody, InfoMessageBody, Message, MoveMessageBody, PheromonLayerBody},
+};
+
+use super::logic::default_pheromon_layers;
 
 /// The player information (position, status, ...).
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct PlayerInfo {
+    /// Public identifier of the player (its joining rank), can be sent to the other players.
+    pub id: u32,
+
     pub position: (u32, u32),
     pub has_food: bool,
+
+    /// Amount of food the player brought back to the nest.
+    pub food_delivered: u32,
+
+    /// Team of the player, which is also the index of its nest.
+    pub team: u32,
 }
 
 impl PlayerInfo {
-    /// Create a player information using maze informations.
-    pub fn new(maze: &Maze) -> Self {
+    /// Create a player information using maze informations, the player starts at its team nest.
+    pub fn new(maze: &Maze, id: u32, team: u32) -> Self {
+        let position = maze
+            .get_nest(team)
+            .map(|nest| (nest.column, nest.line))
+            .unwrap_or((maze.nest_column, maze.nest_line));
+
         Self {
-            position: (maze.nest_column, maze.nest_line),
+            id,
+            position,
             has_food: false,
+            food_delivered: 0,
+            team,
+        }
+    }
+}
+
+/// When the players drop pheromon on a [`PheromonLayer`].
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PheromonDrop {
+    /// While carrying food, makes a trail from the food to the nest.
+    CarryingFood,
+    /// While searching for food, makes a trail from the nest to the explored tiles.
+    SearchingFood,
+}
+
+/// A named pheromon grid, with its own drop and evaporation rules.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PheromonLayer {
+    pub name: Box<str>,
+    pub drop: PheromonDrop,
+    pub drop_amount: f32,
+    pub evaporation_rate: f32,
+
+    /// `levels` may be sent though channels, use [`Arc::make_mut`] to make this object mutable
+    /// as needed while not needing to duplicate the whole vector each time we need a copy
+    /// of it by following a clone-on-write behaviour.
+    #[allow(clippy::redundant_allocation)]
+    // Needs to be boxed for Arc::make_mut() (makes [f32] Clone).
+    pub levels: Arc<Box<[f32]>>,
+}
+
+/// Checksums of a [`GameState`], used to check that a replay matches the recorded game.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StateChecksum {
+    /// Checksum of the players (positions, carried food and delivered food).
+    pub players: u64,
+    /// Checksum of all the pheromon layers.
+    pub pheromon: u64,
+}
+
+/// FNV-1a (64 bits) hasher, unlike the std hashers it is stable across versions and platforms.
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+
+    fn new() -> Self {
+        Self(Self::OFFSET_BASIS)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ *byte as u64).wrapping_mul(Self::PRIME);
         }
     }
 }
 
 /// Game state structure.
 /// This structure contains the immediate and serializable state of a game, that will change during the game life.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct GameState {
     /// Consider [`PlayerInfo`] as immutable in functions.
     pub players: HashMap<Uuid, PlayerInfo>,
     pub maze: Maze,
 
-    /// `pheromon` may be sent though channels, use [`Arc::make_mut`] to make this object mutable
-    /// as needed while not needing to duplicate the whole vector each time we need a copy
-    /// of it by following a clone-on-write behaviour.
-    #[allow(clippy::redundant_allocation)]
-    // Needs to be boxed for Arc::make_mut() (makes [f32] Clone).
-    pub pheromon: Arc<Box<[f32]>>,
+    /// Pheromon layers, the first one is sent as [`InfoMessageBody::pheromon`] to the clients.
+    pub pheromon: Vec<PheromonLayer>,
+
+    /// Amount of food brought back to the nest by all the players.
+    pub food_delivered: u32,
+
+    /// Amount of food brought back to the nest by each team.
+    pub team_food_delivered: Box<[u32]>,
+
+    /// Amount of food left on each tile of the maze, follows the [`Maze::tiles`] ordering.
+    pub food: Box<[u32]>,
+
+    /// Food sources that got depleted since the last [`GameState::take_depleted_food`].
+    pub depleted_food: Vec<(u32, u32)>,
+
+    /// Players in joining order, the moves of a tick are resolved in this order.
+    pub player_order: Vec<Uuid>,
+
+    /// Amount of ticks elapsed (tick-based mode).
+    pub tick: u64,
+
+    /// Moves queued for the next tick, the last one sent by a player wins (tick-based mode).
+    #[serde(default)]
+    queued_moves: HashMap<Uuid, MoveMessageBody>,
+
+    /// Tiles revealed by each player, follows the [`Maze::tiles`] ordering (fog of war).
+    #[serde(default)]
+    pub revealed: HashMap<Uuid, Box<[bool]>>,
+
+    pub config: GameConfig,
 }
 
 impl GameState {
     /// Create a new initial [`GameState`] from a specific [`Maze`].
-    pub fn new(maze: Maze) -> Self {
-        let pheromon = vec![0f32; maze.nb_column as usize * maze.nb_line as usize];
+    pub fn new(maze: Maze, config: GameConfig) -> Self {
+        let pheromon = default_pheromon_layers(
+            maze.nb_column as usize * maze.nb_line as usize,
+            &config.pheromon,
+        );
+
+        // Fill each food source, endless ones are never decremented.
+        let food = maze
+            .tiles
+            .iter()
+            .map(|tile| match Tile::from(*tile).is_food() {
+                true => config.food_quantity.unwrap_or(u32::MAX),
+                false => 0,
+            })
+            .collect();
+
+        let team_food_delivered = vec![0; maze.team_count() as usize].into_boxed_slice();
 
         GameState {
             maze,
             players: HashMap::with_capacity(5),
-            pheromon: pheromon.into_boxed_slice().into(),
+            pheromon,
+            food_delivered: 0,
+            team_food_delivered,
+            food,
+            depleted_food: vec![],
+            player_order: vec![],
+            tick: 0,
+            queued_moves: HashMap::new(),
+            revealed: HashMap::new(),
+            config,
         }
     }
 
+    /// Take the food sources that got depleted since the last call.
+    pub fn take_depleted_food(&mut self) -> Vec<(u32, u32)> {
+        std::mem::take(&mut self.depleted_food)
+    }
+
+    /// Get all the food sources that are depleted.
+    pub fn get_depleted_food(&self) -> impl Iterator<Item = (u32, u32)> + '_ {
+        self.maze
+            .tiles
+            .iter()
+            .zip(self.food.iter())
+            .enumerate()
+            .filter(|(_, (tile, quantity))| Tile::from(**tile).is_food() && **quantity == 0)
+            .map(|(i, _)| {
+                (
+                    (i as u32) % self.maze.nb_column,
+                    (i as u32) / self.maze.nb_column,
+                )
+            })
+    }
+
+    /// Build the game view of a player.
+    pub fn info_message(&self, info: &PlayerInfo) -> InfoMessageBody {
+        let perceived = self.perception(info);
+
+        InfoMessageBody {
+            player_column: info.position.0,
+            player_line: info.position.1,
+            player_has_food: info.has_food,
+            pheromon: self
+                .pheromon
+                .first()
+                .map(|layer| Self::perceived_levels(&layer.levels, perceived.as_deref()))
+                .unwrap_or_default(),
+            pheromon_layers: self
+                .pheromon
+                .iter()
+                .skip(1)
+                .map(|layer| PheromonLayerBody {
+                    name: layer.name.clone(),
+                    pheromon: Self::perceived_levels(&layer.levels, perceived.as_deref()),
+                })
+                .collect(),
+            pheromon_deltas: vec![],
+            player_food_delivered: info.food_delivered,
+            total_food_delivered: self.food_delivered,
+            player_team: info.team,
+            team_food_delivered: self
+                .team_food_delivered
+                .get(info.team as usize)
+                .copied()
+                .unwrap_or_default(),
+            ants: self.visible_ants(info, perceived.as_deref()).collect(),
+            tiles: perceived
+                .iter()
+                .flatten()
+                .map(|index| self.tile_body(*index))
+                .collect(),
+        }
+    }
+
+    /// Get the other ants the player can see, see [`AntVisibility`],
+    /// with the fog of war only the ants on the `perceived` tiles are seen.
+    fn visible_ants<'a>(
+        &'a self,
+        info: &'a PlayerInfo,
+        perceived: Option<&'a [usize]>,
+    ) -> impl Iterator<Item = AntBody> + 'a {
+        let max_distance = match self.config.ant_visibility {
+            AntVisibility::Hidden => None,
+            AntVisibility::Colony => Some(u32::MAX),
+            AntVisibility::Nearby(distance) => Some(distance),
+        };
+
+        self.player_order
+            .iter()
+            .filter_map(|uuid| self.players.get(uuid))
+            .filter(move |other| {
+                let distance = other.position.0.abs_diff(info.position.0)
+                    + other.position.1.abs_diff(info.position.1);
+
+                let index = (other.position.0 + other.position.1 * self.maze.nb_column) as usize;
+
+                other.id != info.id
+                    && max_distance.is_some_and(|max| distance <= max)
+                    && perceived.is_none_or(|perceived| perceived.contains(&index))
+            })
+            .map(|other| AntBody {
+                id: other.id,
+                team: other.team,
+                column: other.position.0,
+                line: other.position.1,
+                has_food: other.has_food,
+            })
+    }
+
+    /// Add a new player to the game, in the team with the fewest players.
+    pub fn add_player(&mut self, uuid: Uuid) -> PlayerInfo {
+        let mut team_sizes = vec![0u32; self.maze.team_count() as usize];
+
+        for player in self.players.values() {
+            if let Some(size) = team_sizes.get_mut(player.team as usize) {
+                *size += 1;
+            }
+        }
+
+        // Take the first smallest team.
+        let team = (0..team_sizes.len())
+            .min_by_key(|team| team_sizes[*team])
+            .unwrap_or_default() as u32;
+
+        let info = PlayerInfo::new(&self.maze, self.player_order.len() as u32, team);
+        self.players.insert(uuid, info);
+        self.player_order.push(uuid);
+        self.reveal(&uuid);
+
+        info
+    }
+
     /// Process a [`Message`] for the player identified by [`Uuid`].
     pub fn process_message(
         &mut self,
@@ -56,10 +306,7 @@ impl GameState {
         msg: &Message,
     ) -> Result<PlayerInfo, ServerError> {
         // Do note that this is a copy of the player info.
-        let player_info = *self
-            .players
-            .get(uuid)
-            .expect("Player is missing from the game state.");
+        let player_info = *self.players.get(uuid).ok_or(ServerError::ExpiredUuid)?;
 
         match msg {
             Message::Move(move_msg) => {
@@ -71,9 +318,141 @@ impl GameState {
                     .get_mut(uuid)
                     .expect("Player has disappeared during process_movement() ?") = new_player_info;
 
+                self.reveal(uuid);
+
                 Ok(new_player_info)
             }
             _ => Err(ServerError::UnexpectedParameter),
         }
     }
+
+    /// Compute the [`StateChecksum`] of the game.
+    pub fn checksum(&self) -> StateChecksum {
+        let mut players = Fnv1a::new();
+
+        for uuid in self.player_order.iter() {
+            if let Some(info) = self.players.get(uuid) {
+                players.write(uuid.as_bytes());
+                players.write(&info.position.0.to_le_bytes());
+                players.write(&info.position.1.to_le_bytes());
+                players.write(&[info.has_food as u8]);
+                players.write(&info.food_delivered.to_le_bytes());
+            }
+        }
+
+        let mut pheromon = Fnv1a::new();
+
+        for layer in self.pheromon.iter() {
+            for level in layer.levels.iter() {
+                pheromon.write(&level.to_bits().to_le_bytes());
+            }
+        }
+
+        StateChecksum {
+            players: players.0,
+            pheromon: pheromon.0,
+        }
+    }
+
+    /// Queue a [`Message`] of the player identified by [`Uuid`] for the next tick.
+    pub fn queue_message(&mut self, uuid: &Uuid, msg: &Message) -> Result<(), ServerError> {
+        match msg {
+            Message::Move(move_msg) => {
+                self.queued_moves.insert(*uuid, move_msg.clone());
+
+                Ok(())
+            }
+            _ => Err(ServerError::UnexpectedParameter),
+        }
+    }
+
+    /// Run a tick : resolve the queued moves in joining order,
+    /// then update the pheromon if a pheromon update is due.
+    pub fn step_tick(&mut self) {
+        for index in 0..self.player_order.len() {
+            let uuid = self.player_order[index];
+
+            if let Some(move_msg) = self.queued_moves.remove(&uuid) {
+                // Can't fail, the message is a move.
+                let _ = self.process_message(&uuid, &Message::Move(move_msg));
+            }
+        }
+
+        self.tick += 1;
+
+        let pheromon_update_ticks = self
+            .config
+            .tick
+            .map_or(0, |tick| tick.pheromon_update_ticks as u64);
+
+        if pheromon_update_ticks > 0 && self.tick.is_multiple_of(pheromon_update_ticks) {
+            self.update_pheromon();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ai::{probabilistic::ProbabilisticAnt, AntAI},
+        config::{GeneratorConfig, TickConfig},
+        maze::generator::generate_maze,
+        message::types::JoinMessageBody,
+    };
+
+    /// Run a tick-based game of random ants, everything being generated from `seed`.
+    fn run_game(seed: u64) -> StateChecksum {
+        let rng = fastrand::Rng::with_seed(seed);
+
+        let maze = generate_maze(
+            &GeneratorConfig::default(),
+            &JoinMessageBody {
+                difficulty: 2,
+                player_id: None,
+                room: None,
+                pheromon_encoding: Default::default(),
+            },
+            &rng,
+        )
+        .unwrap();
+
+        let mut state = GameState::new(
+            maze.clone(),
+            GameConfig {
+                tick: Some(TickConfig {
+                    pheromon_update_ticks: 5,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        );
+
+        let players: Vec<Uuid> = (0..4).map(Uuid::from_u128).collect();
+
+        for uuid in players.iter() {
+            state.add_player(*uuid);
+        }
+
+        let mut ai = ProbabilisticAnt;
+
+        for _ in 0..200 {
+            for uuid in players.iter() {
+                let info = state.info_message(&state.players[uuid]);
+
+                if let Some(movement) = ai.step(&maze, &info, &rng) {
+                    state.queue_message(uuid, &Message::Move(movement)).unwrap();
+                }
+            }
+
+            state.step_tick();
+        }
+
+        state.checksum()
+    }
+
+    #[test]
+    fn same_seed_same_game() {
+        assert_eq!(run_game(42), run_game(42));
+    }
 }
@@ -1,40 +1,138 @@
 //! The client session management as seen from the lobby.
-use std::sync::mpsc::{self, Receiver, Sender};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    },
+    time::{Duration, Instant},
+};
 
 use uuid::Uuid;
 
 use crate::{
+    config::HeartbeatConfig,
     error::ServerError,
-    game::{GameSessionMessage, GameSessionMessageKind},
-    lobby::message::{LobbyMessage, MatchmakingInfo},
-    message::types::{JoinMessageBody, Message, OkMazeMessageBody},
-    protocols::PlayerChannel,
+    game::{record::GameRecord, GameSessionMessage, GameSessionMessageKind},
+    lobby::message::{ClientCapabilities, LobbyMessage, MatchmakingInfo},
+    message::{
+        transmit::WireEncoding,
+        types::{
+            Capability, CreateRoomMessageBody, HeartbeatMessageBody, HelloMessageBody,
+            JoinMessageBody, Message, OkMazeMessageBody, PheromonEncoding, RoomCreatedMessageBody,
+            WatchReplayMessageBody, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION, SUPPORTED_CAPABILITIES,
+        },
+    },
+    protocols::{encoded::EncodedChannel, PlayerChannel},
+    record,
 };
 
+/// Id of the next game connection, see [`GameSessionMessageKind::Disconnect`].
+/// The AI players use `0`.
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Delay given to a client to send each of its messages before joining a game or a replay.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
 /// Instanciate a client negociation with with the lobby.
+/// The client is answered using the [`WireEncoding`] of its first message (only if it
+/// negotiated [`Capability::MessagePack`] when it says hello), and pinged during the game
+/// if it negotiated [`Capability::Heartbeat`].
 pub fn client_session_init<C: PlayerChannel>(
     mut client: C,
     channel: Sender<LobbyMessage>,
+    heartbeat: Option<HeartbeatConfig>,
 ) -> Result<(), ServerError> {
-    let res = match client.read_message() {
-        // Received join
-        Ok(Message::Join(body)) => client_session_negociate(client.clone_instance(), channel, body),
-
-        // Received something else
-        // Send Unexpected message error to client.
-        Ok(unexpected) => {
-            client.write_message(&Message::Unexpected {
-                expected: vec!["join".into()],
-                received: unexpected.into(),
-            })?;
-
-            Err(ServerError::Transmission(
-                "Unexpected message received".into(),
-            ))
-        }
+    client.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+
+    let first_message = client.read_message_encoded();
+    let encoding = first_message
+        .as_ref()
+        .map_or(WireEncoding::Json, |(_, encoding)| *encoding);
+
+    let mut client = EncodedChannel::new(client, encoding);
+    let mut first_message = Some(first_message.map(|(message, _)| message));
+
+    // Negotiated capabilities, `None` for the legacy clients that don't say hello.
+    let mut hello: Option<HelloMessageBody> = None;
+
+    let res = loop {
+        let message = match first_message.take() {
+            Some(message) => message,
+            None => client.read_message(),
+        };
+
+        match message {
+            // Received hello, negotiate the protocol before anything else.
+            Ok(Message::Hello(body)) if hello.is_none() => {
+                match client_session_hello(&mut client, body) {
+                    Ok(negotiated) => hello = Some(negotiated),
+                    Err(err) => break Err(err),
+                }
+            }
 
-        // Something went wrong during read_message()
-        Err(err) => Err(err),
+            // Received join
+            Ok(Message::Join(mut body)) => {
+                // Fall back to the whole pheromon grids if the client can't handle deltas.
+                if hello
+                    .as_ref()
+                    .is_some_and(|hello| !hello.supports(Capability::PheromonDeltas))
+                {
+                    body.pheromon_encoding = PheromonEncoding::Full;
+                }
+
+                break client_session_negociate(
+                    client.clone_instance(),
+                    channel,
+                    body,
+                    heartbeat,
+                    hello.map(|hello| hello.capabilities),
+                );
+            }
+
+            // Received ping
+            Ok(Message::Ping(body)) => {
+                if let Err(err) = client.write_message(&Message::Pong(body)) {
+                    break Err(err);
+                }
+            }
+
+            // Received watch replay
+            Ok(Message::WatchReplay(body)) => {
+                break client_session_replay(client.clone_instance(), channel, body)
+            }
+
+            // Received create room, wait for the client to join afterward.
+            Ok(Message::CreateRoom(body)) => {
+                let capabilities = hello.as_ref().map(|hello| hello.capabilities.clone());
+
+                if let Err(err) =
+                    client_session_create_room(&mut client, &channel, body, capabilities)
+                {
+                    break Err(err);
+                }
+            }
+
+            // Received something else
+            // Send Unexpected message error to client.
+            Ok(unexpected) => {
+                client.write_message(&Message::Unexpected {
+                    expected: vec![
+                        "hello".into(),
+                        "join".into(),
+                        "createRoom".into(),
+                        "watchReplay".into(),
+                    ],
+                    received: unexpected.into(),
+                })?;
+
+                break Err(ServerError::BadMessage(
+                    "Unexpected message received".into(),
+                ));
+            }
+
+            // Something went wrong during read_message()
+            Err(err) => break Err(err),
+        }
     };
 
     if let Err(err) = &res {
@@ -52,26 +150,120 @@ pub fn client_session_init<C: PlayerChannel>(
     }
 }
 
-/// Negociate a game session with the lobby.
+/// Check the protocol version of the client, then answer with the server version and
+/// the capabilities supported by both, returns the answer.
+/// The client is answered in JSON from now on if it can't handle MessagePack.
+fn client_session_hello<C: PlayerChannel>(
+    client: &mut EncodedChannel<C>,
+    body: HelloMessageBody,
+) -> Result<HelloMessageBody, ServerError> {
+    if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&body.version) {
+        return Err(ServerError::IncompatibleVersion {
+            version: body.version,
+            min_version: MIN_PROTOCOL_VERSION,
+            max_version: PROTOCOL_VERSION,
+        });
+    }
+
+    let hello = HelloMessageBody {
+        version: PROTOCOL_VERSION,
+        capabilities: SUPPORTED_CAPABILITIES
+            .iter()
+            .copied()
+            .filter(|capability| body.capabilities.contains(capability))
+            .collect(),
+    };
+
+    if !hello.supports(Capability::MessagePack) {
+        client.set_encoding(WireEncoding::Json);
+    }
+
+    client.write_message(&Message::Hello(hello.clone()))?;
+
+    Ok(hello)
+}
+
+/// Ask the lobby to create a private room, then send the room code to the client.
+fn client_session_create_room<C: PlayerChannel>(
+    client: &mut C,
+    sender: &Sender<LobbyMessage>,
+    body: CreateRoomMessageBody,
+    capabilities: ClientCapabilities,
+) -> Result<(), ServerError> {
+    let (tx, rx) = mpsc::channel();
+
+    sender.send(LobbyMessage::CreateRoom(body, capabilities, tx.into()))?;
+
+    let code = rx.recv()??;
+    println!("Created room {code}");
+
+    client.write_message(&Message::RoomCreated(RoomCreatedMessageBody { code }))
+}
+
+/// Ask the lobby for the record of the game, then stream its replay to the client.
+fn client_session_replay<C: PlayerChannel>(
+    mut client: C,
+    sender: Sender<LobbyMessage>,
+    body: WatchReplayMessageBody,
+) -> Result<(), ServerError> {
+    // The watcher only sends the playback controls, whenever it wants.
+    client.set_read_timeout(None)?;
+
+    let (tx, rx) = mpsc::channel();
+
+    sender.send(LobbyMessage::FindRecord(body.id, tx.into()))?;
+
+    let path = rx.recv()??;
+    println!("Replaying {}", path.display());
+
+    record::replay_game(client, GameRecord::load(&path)?, body.speed.unwrap_or(1.0))
+}
+
+/// Negociate a game session with the lobby, the client is pinged and disconnected when silent
+/// if it negotiated [`Capability::Heartbeat`].
 fn client_session_negociate<C: PlayerChannel>(
     mut client: C,
     sender: Sender<LobbyMessage>,
     body: JoinMessageBody,
+    heartbeat: Option<HeartbeatConfig>,
+    capabilities: ClientCapabilities,
 ) -> Result<(), ServerError> {
     println!("Started client session (UUID = {:?})", body.player_id);
 
     let (tx, rx) = mpsc::channel();
+    let pheromon_encoding = body.pheromon_encoding;
 
-    sender.send(LobbyMessage::Matchmaking(body, tx.into()))?;
+    let pinged = capabilities
+        .as_ref()
+        .is_some_and(|capabilities| capabilities.contains(&Capability::Heartbeat));
+
+    // Only the legacy clients may join a fog of war game without negotiating it.
+    let fog_of_war = capabilities
+        .as_ref()
+        .is_none_or(|capabilities| capabilities.contains(&Capability::FogOfWar));
+
+    sender.send(LobbyMessage::Matchmaking(body, capabilities, tx.into()))?;
 
     // receive matchmaking information from lobby
     // that way, we get the ok maze that also contains the player UUID used internally
     match rx.recv() {
+        // The client can't play the game of its room code or UUID, give its room back.
+        Ok(MatchmakingInfo::JoinedGame(uuid, game_session))
+            if game_session.fog_of_war() && !fog_of_war =>
+        {
+            sender.send(LobbyMessage::Leave(uuid)).ok();
+
+            client.write_message(&Message::Error(ServerError::MissingCapability(
+                Capability::FogOfWar,
+            )))?
+        }
+
         // Ok with OkMaze
         Ok(MatchmakingInfo::JoinedGame(uuid, game_session)) => {
             client.write_message(&Message::OkMaze(OkMazeMessageBody {
-                maze: game_session.maze.clone(),
+                maze: game_session.client_maze(),
                 player_id: uuid,
+                pheromon_encoding,
             }))?;
 
             // Split the client session in two parts:
@@ -88,27 +280,61 @@ fn client_session_negociate<C: PlayerChannel>(
 
             // Fetch the game session channel then notify the game session of this new player.
             let game_session_channel = game_session.channel.lock()?.clone();
+            let connection = NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed);
 
             game_session_channel.send(GameSessionMessage(
                 uuid,
-                GameSessionMessageKind::InitializePlayer(sender_tx),
+                GameSessionMessageKind::InitializePlayer(sender_tx, pheromon_encoding, connection),
             ))?;
 
+            // Disconnect the pinged client if it stays silent, it's pinged more often than that.
+            // The other clients can't answer the pings, they may stay idle.
+            let heartbeat = heartbeat.filter(|_| pinged);
+
+            client.set_read_timeout(
+                heartbeat.map(|heartbeat| Duration::from_millis(heartbeat.timeout)),
+            )?;
+
+            let ping_delay = heartbeat.map(|heartbeat| Duration::from_millis(heartbeat.ping_delay));
+
             std::thread::Builder::new()
                 .name(format!(
                     "client send {}",
                     client.get_name().unwrap_or_default()
                 ))
-                .spawn(move || client_session_send_loop(&mut sender_client, sender_rx))?;
+                .spawn(move || {
+                    client_session_send_loop(&mut sender_client, sender_rx, ping_delay)
+                })?;
+
+            // Receiver loop, the player can reconnect once it ends (e.g. timed out).
+            let res = client_session_recv_loop(&mut client, &game_session_channel, uuid);
 
-            // Receiver loop
-            client_session_recv_loop(&mut client, game_session_channel, uuid)?;
+            game_session_channel
+                .send(GameSessionMessage(
+                    uuid,
+                    GameSessionMessageKind::Disconnect(connection),
+                ))
+                .ok();
+
+            sender.send(LobbyMessage::Leave(uuid)).ok();
+
+            res?;
         }
 
         // UUID is not recognized by lobby.
-        Ok(MatchmakingInfo::ExpiredUuid) => client.write_message(&Message::Error(
-            ServerError::Other("Invalid UUID or game doesn't exist anymore.".into()),
-        ))?,
+        Ok(MatchmakingInfo::ExpiredUuid) => {
+            client.write_message(&Message::Error(ServerError::ExpiredUuid))?
+        }
+
+        // Room code is not recognized by lobby.
+        Ok(MatchmakingInfo::UnknownRoom) => {
+            client.write_message(&Message::Error(ServerError::UnknownRoom))?
+        }
+
+        // No room left in the game.
+        Ok(MatchmakingInfo::GameFull) => {
+            client.write_message(&Message::Error(ServerError::GameFull))?
+        }
 
         // Internal failures.
         Ok(MatchmakingInfo::InternalFailure(e)) => client.write_message(&Message::Error(e))?,
@@ -119,14 +345,20 @@ fn client_session_negociate<C: PlayerChannel>(
 }
 
 /// Client [`Message`] (from [`GameSessionMessage`]) receiving loop.
+/// The pings are answered by the game session, so that only the sending loop writes to the client.
 fn client_session_recv_loop<C: PlayerChannel>(
     client: &mut C,
-    channel: Sender<GameSessionMessage>,
+    channel: &Sender<GameSessionMessage>,
     uuid: Uuid,
 ) -> Result<(), ServerError> {
     loop {
         let msg = client.read_message()?;
 
+        // Answers to our pings only prove that the client is alive.
+        if let Message::Pong(_) = msg {
+            continue;
+        }
+
         channel.send(GameSessionMessage(
             uuid,
             GameSessionMessageKind::ClientMessage(msg),
@@ -134,14 +366,40 @@ fn client_session_recv_loop<C: PlayerChannel>(
     }
 }
 
-/// Client [`Message`] sending loop.
+/// Client [`Message`] sending loop, pinging the client every `ping_delay` (if any).
 fn client_session_send_loop<C: PlayerChannel>(
     client: &mut C,
     receiver: Receiver<Message>,
+    ping_delay: Option<Duration>,
 ) -> Result<(), ServerError> {
+    let mut ping = HeartbeatMessageBody { id: 0 };
+    let mut last_ping = Instant::now();
+
     loop {
-        let msg = receiver.recv()?;
+        let msg = match ping_delay {
+            Some(delay) => {
+                if last_ping.elapsed() >= delay {
+                    ping.id += 1;
+                    last_ping = Instant::now();
+
+                    client.write_message(&Message::Ping(ping.clone()))?;
+                }
+
+                receiver.recv_timeout(delay.saturating_sub(last_ping.elapsed()))
+            }
+            None => receiver.recv().map_err(RecvTimeoutError::from),
+        };
 
-        client.write_message(&msg)?;
+        match msg {
+            Ok(msg) => client.write_message(&msg)?,
+
+            // Time to ping the client.
+            Err(RecvTimeoutError::Timeout) => continue,
+
+            // Stop the client once the game session is over (it drops its sender).
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
     }
+
+    client.stop()
 }
@@ -1,9 +1,16 @@
 //! Types of messages that the lobby internally uses.
-use std::sync::{mpsc::Sender, Arc, Mutex};
+use std::{
+    path::PathBuf,
+    sync::{mpsc::Sender, Arc, Mutex},
+};
 
 use uuid::Uuid;
 
-use crate::{error::ServerError, game::GameSessionInfo, message::types::JoinMessageBody};
+use crate::{
+    error::ServerError,
+    game::GameSessionInfo,
+    message::types::{Capability, CreateRoomMessageBody, JoinMessageBody},
+};
 
 /// Message sent by the lobby thread to a client thread to indicate that
 /// the client has joined (or not) the game (specified by [`MatchmakingInfo::JoinedGame`]).
@@ -11,11 +18,31 @@ use crate::{error::ServerError, game::GameSessionInfo, message::types::JoinMessa
 pub enum MatchmakingInfo {
     JoinedGame(Uuid, Arc<GameSessionInfo>),
     ExpiredUuid,
+    UnknownRoom,
+    GameFull,
     InternalFailure(ServerError),
 }
 
+/// Capabilities negotiated by a client, `None` for the legacy clients that don't say hello.
+pub type ClientCapabilities = Option<Vec<Capability>>;
+
 /// Message sent by the client thread or housekeeping timer thread to the lobby thread.
 pub enum LobbyMessage {
-    Matchmaking(JoinMessageBody, Mutex<Sender<MatchmakingInfo>>),
+    /// Find a game for the client, matchmaking only picks the games the client can play.
+    Matchmaking(
+        JoinMessageBody,
+        ClientCapabilities,
+        Mutex<Sender<MatchmakingInfo>>,
+    ),
+    /// Create a private room, the lobby answers with the room code.
+    CreateRoom(
+        CreateRoomMessageBody,
+        ClientCapabilities,
+        Mutex<Sender<Result<Box<str>, ServerError>>>,
+    ),
+    /// Find the record of a game session, the lobby answers with the record path.
+    FindRecord(Uuid, Mutex<Sender<Result<PathBuf, ServerError>>>),
+    /// A connection of the player to its game ended, the player can still reconnect.
+    Leave(Uuid),
     Housekeep,
 }
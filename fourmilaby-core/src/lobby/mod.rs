@@ -4,6 +4,7 @@ pub mod message;
 
 use std::{
     collections::HashMap,
+    path::PathBuf,
     sync::{
         self,
         mpsc::{self, Sender},
@@ -15,27 +16,75 @@ use std::{
 
 use crate::{
     ai::{probabilistic::ProbabilisticAnt, AntGroup},
-    config::LobbyConfig,
+    config::{GameConfig, HeartbeatConfig, LobbyConfig},
     error::ServerError,
-    game::{state::GameState, GameSession, GameSessionInfo},
+    game::{
+        record::RecordOptions,
+        snapshot::{SessionSnapshot, SnapshotOptions},
+        state::GameState,
+        GameSession, GameSessionInfo,
+    },
     maze::generator::generate_maze,
-    message::types::JoinMessageBody,
+    message::types::{Capability, JoinMessageBody},
     protocols::{LobbyListener, PlayerChannel},
 };
-use message::{LobbyMessage, MatchmakingInfo};
+use message::{ClientCapabilities, LobbyMessage, MatchmakingInfo};
 
 use uuid::Uuid;
 
 const LOBBY_HOUSEKEEP_DELAY: Duration = Duration::from_secs(5);
 
+/// Amount of AI players put in each game, and the delay between each of their moves.
+const AI_PLAYERS: usize = 10;
+const AI_PERIOD: Duration = Duration::from_millis(1000);
+
+/// Characters used in room codes, without the easily confused ones (0/O, 1/I).
+const ROOM_CODE_CHARSET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const ROOM_CODE_LENGTH: usize = 6;
+
+/// A type-erased listener accept loop, feeding the lobby channel.
+type ListenerLoop = Box<dyn FnOnce(Sender<LobbyMessage>) -> Result<(), ServerError> + Send>;
+
+/// A game known by the lobby, along with the criteria it was created with.
+struct LobbyGame {
+    session: sync::Weak<GameSessionInfo>,
+    difficulty: u32,
+
+    /// Room code if the game is a private room, private rooms are kept out of matchmaking.
+    room: Option<Box<str>>,
+
+    /// Amount of live connections of each player that joined this game through matchmaking,
+    /// only the connected players take up room in the game.
+    connections: HashMap<Uuid, u32>,
+}
+
+impl LobbyGame {
+    fn new(session: &Arc<GameSessionInfo>, difficulty: u32, room: Option<Box<str>>) -> Self {
+        Self {
+            session: Arc::downgrade(session),
+            difficulty,
+            room,
+            connections: HashMap::new(),
+        }
+    }
+
+    /// Amount of players connected to this game.
+    fn player_count(&self) -> u32 {
+        self.connections.len() as u32
+    }
+}
+
 pub struct Lobby {
     // Weak pointers allows us to know if a game session is still alive.
     // However, we will have to housekeep those collections to prevent memory from leaking
     // by unfreed weak pointers.
-    games: Vec<sync::Weak<GameSessionInfo>>,
+    games: Vec<LobbyGame>,
     players: HashMap<Uuid, sync::Weak<GameSessionInfo>>,
     config: LobbyConfig,
     rng: fastrand::Rng,
+
+    /// Listeners with their binding name, started by [`Lobby::run`].
+    listeners: Vec<(String, ListenerLoop)>,
 }
 
 impl Lobby {
@@ -44,14 +93,33 @@ impl Lobby {
         Lobby {
             games: Vec::with_capacity(4),
             players: HashMap::with_capacity(64),
+            rng: config
+                .seed
+                .map_or_else(fastrand::Rng::new, fastrand::Rng::with_seed),
             config,
-            rng: fastrand::Rng::new(),
+            listeners: vec![],
         }
     }
 
+    /// Add a listener to the lobby, every listener gets its own accept thread.
+    pub fn add_listener<C: PlayerChannel, L: LobbyListener<C>>(&mut self, listener: L) {
+        let name = listener
+            .get_binding_name()
+            .map(|name| name.into_owned())
+            .unwrap_or_default();
+
+        let heartbeat = self.config.heartbeat;
+
+        self.listeners.push((
+            name,
+            Box::new(move |sender| Self::lobby(&sender, listener, heartbeat)),
+        ));
+    }
+
     fn lobby<C: PlayerChannel, L: LobbyListener<C>>(
         send: &Sender<LobbyMessage>,
         mut listener: L,
+        heartbeat: Option<HeartbeatConfig>,
     ) -> Result<(), ServerError> {
         loop {
             let (stream, addr) = listener.accept_client()?;
@@ -62,21 +130,19 @@ impl Lobby {
             // Create a new client session
             thread::Builder::new()
                 .name(format!("client session {}", addr))
-                .spawn(move || handler::client_session_init(stream, channel))
+                .spawn(move || handler::client_session_init(stream, channel, heartbeat))
                 .unwrap();
         }
     }
 
-    pub fn run<C: PlayerChannel, L: LobbyListener<C>>(
-        mut self,
-        listener: L,
-    ) -> Result<(), ServerError> {
-        if let Some(name) = listener.get_binding_name() {
-            println!("Lobby loop listening on {name}");
-        } else {
-            println!("Lobby loop listening");
+    /// Run the lobby loop, accepting clients from all the listeners.
+    pub fn run(mut self) -> Result<(), ServerError> {
+        if self.listeners.is_empty() {
+            return Err(ServerError::Other("No listener in lobby".into()));
         }
 
+        self.restore_games();
+
         let (sender, receiver) = mpsc::channel::<LobbyMessage>();
 
         let housekeep_sender = sender.clone();
@@ -88,29 +154,95 @@ impl Lobby {
                 housekeep_sender.send(LobbyMessage::Housekeep).unwrap();
             })?;
 
-        thread::Builder::new()
-            .name(String::from("lobby accept"))
-            .spawn(move || Self::lobby(&sender, listener).unwrap())?;
+        for (name, listener) in self.listeners.drain(..) {
+            println!("Lobby loop listening on {name}");
+
+            let listener_sender = sender.clone();
+
+            thread::Builder::new()
+                .name(format!("lobby accept {name}"))
+                .spawn(move || {
+                    if let Err(err) = listener(listener_sender) {
+                        eprintln!("Listener {name} stopped : {err}");
+                    }
+                })?;
+        }
 
         loop {
             let msg = receiver.recv().unwrap();
 
             match msg {
-                LobbyMessage::Matchmaking(body, channel) => {
-                    let info = self.find_suitable_game(&body);
+                LobbyMessage::Matchmaking(body, capabilities, channel) => {
+                    let info = self.matchmaking(&body, &capabilities);
 
-                    channel.lock().unwrap().send(info.clone())?;
+                    channel.lock().unwrap().send(info)?;
+                }
+                LobbyMessage::CreateRoom(body, capabilities, channel) => {
+                    let code = self.create_room(body.difficulty, self.fog_of_war(&capabilities));
 
-                    // Register player UUID if it gets connected.
-                    if let MatchmakingInfo::JoinedGame(uuid, session) = info {
-                        self.players.insert(uuid, Arc::downgrade(&session));
-                    }
+                    channel.lock().unwrap().send(code)?;
+                }
+                LobbyMessage::FindRecord(id, channel) => {
+                    channel.lock().unwrap().send(self.find_record(&id))?;
                 }
+                LobbyMessage::Leave(uuid) => self.leave(&uuid),
                 LobbyMessage::Housekeep => self.housekeep(),
             }
         }
     }
 
+    /// Find a game for the player, and register its connection if it joined one.
+    fn matchmaking(
+        &mut self,
+        join_message: &JoinMessageBody,
+        capabilities: &ClientCapabilities,
+    ) -> MatchmakingInfo {
+        let info = self.find_suitable_game(join_message, self.fog_of_war(capabilities));
+
+        if let MatchmakingInfo::JoinedGame(uuid, session) = &info {
+            self.players.insert(*uuid, Arc::downgrade(session));
+
+            if let Some(entry) = self.game_entry(session) {
+                *entry.connections.entry(*uuid).or_default() += 1;
+            }
+        }
+
+        info
+    }
+
+    /// A connection of the player ended, free its room in the game once it has none left.
+    fn leave(&mut self, player_uuid: &Uuid) {
+        let Some(session) = self.get_player_game(player_uuid) else {
+            return;
+        };
+
+        if let Some(entry) = self.game_entry(&session) {
+            if let Some(count) = entry.connections.get_mut(player_uuid) {
+                *count -= 1;
+
+                if *count == 0 {
+                    entry.connections.remove(player_uuid);
+                }
+            }
+        }
+    }
+
+    /// Check if the games created for a client have fog of war, the legacy clients play
+    /// the configured games while the others need [`Capability::FogOfWar`].
+    fn fog_of_war(&self, capabilities: &ClientCapabilities) -> bool {
+        self.config.game.fog_of_war.is_some()
+            && capabilities
+                .as_ref()
+                .is_none_or(|capabilities| capabilities.contains(&Capability::FogOfWar))
+    }
+
+    /// Get the lobby entry of a game session.
+    fn game_entry(&mut self, session: &Arc<GameSessionInfo>) -> Option<&mut LobbyGame> {
+        self.games
+            .iter_mut()
+            .find(|entry| std::ptr::eq(entry.session.as_ptr(), Arc::as_ptr(session)))
+    }
+
     /// Get the player game session.
     fn get_player_game(&self, player_uuid: &Uuid) -> Option<Arc<GameSessionInfo>> {
         self.players
@@ -118,33 +250,130 @@ impl Lobby {
             .and_then(|session| session.upgrade())
     }
 
-    // TODO: Player limit test ? Is player allowed ?
-
+    /// Create a new game, with fog of war if `fog_of_war` is set and the config enables it.
     fn create_new_game(
         &mut self,
         critera: &JoinMessageBody,
+        fog_of_war: bool,
     ) -> Result<Arc<GameSessionInfo>, ServerError> {
-        let maze = generate_maze(&self.config.generator, critera, &self.rng)?;
+        // Each game gets its own seed, so its maze can be regenerated from its record.
+        let seed = self.rng.u64(..);
+        let maze = generate_maze(
+            &self.config.generator,
+            critera,
+            &fastrand::Rng::with_seed(seed),
+        )?;
         // TODO: Make a better API, consider modifying critera.
 
-        let session = GameSession::start_new(GameState::new(maze), self.config.record_games);
+        let ai_players: Box<[Uuid]> = (0..AI_PLAYERS).map(|_| Uuid::new_v4()).collect();
+
+        let config = GameConfig {
+            fog_of_war: self.config.game.fog_of_war.filter(|_| fog_of_war),
+            ..self.config.game
+        };
+
+        let session = GameSession::start_new(
+            GameState::new(maze, config),
+            self.config.record_games.then(|| RecordOptions {
+                directory: self.config.record_directory.clone(),
+                generator: self.config.generator,
+                seed: Some(seed),
+            }),
+            self.config
+                .snapshot_directory
+                .clone()
+                .map(|directory| SnapshotOptions {
+                    directory,
+                    delay: Duration::from_secs(self.config.snapshot_delay),
+                    difficulty: critera.difficulty,
+                    room: critera.room.clone(),
+                    ai_players: ai_players.clone(),
+                }),
+        );
 
         if let Ok(info) = &session {
             // Add the game to the list.
-            self.games.push(Arc::downgrade(info));
+            self.games.push(LobbyGame::new(
+                info,
+                critera.difficulty,
+                critera.room.clone(),
+            ));
 
             // Put AI in game.
-            AntGroup::<ProbabilisticAnt>::new(10, info.channel.lock()?.clone(), info.maze.clone())
-                .unwrap()
-                .start(Duration::from_millis(1000))
-                .unwrap();
+            Self::start_ai(info, &ai_players)?;
         }
 
         session
     }
 
+    /// Start the AI controlling the players `ai_players` of the game.
+    fn start_ai(info: &GameSessionInfo, ai_players: &[Uuid]) -> Result<(), ServerError> {
+        AntGroup::<ProbabilisticAnt>::with_players(
+            ai_players,
+            info.channel.lock()?.clone(),
+            info.maze.clone(),
+        )?
+        .start(AI_PERIOD)?;
+
+        Ok(())
+    }
+
+    /// Restore the games snapshotted before the last stop of the server,
+    /// the players can reconnect to them using their UUID.
+    fn restore_games(&mut self) {
+        let directory = match &self.config.snapshot_directory {
+            Some(directory) => directory.clone(),
+            None => return,
+        };
+
+        for (session, snapshot) in SessionSnapshot::load_all(&directory) {
+            let path = SessionSnapshot::path(&directory, &session);
+            let (difficulty, room) = (snapshot.difficulty, snapshot.room.clone());
+            let ai_players = snapshot.ai_players.clone();
+            let players: Vec<Uuid> = snapshot
+                .state
+                .players
+                .keys()
+                .filter(|uuid| !ai_players.contains(uuid))
+                .copied()
+                .collect();
+
+            // The session keeps its UUID, thus its snapshot and its record.
+            let info = match GameSession::restore(
+                session,
+                snapshot,
+                directory.clone(),
+                Duration::from_secs(self.config.snapshot_delay),
+            ) {
+                Ok(info) => info,
+                Err(err) => {
+                    eprintln!("Unable to restore {} : {err}", path.display());
+                    continue;
+                }
+            };
+
+            println!("Restored {} ({} players)", path.display(), players.len());
+
+            for uuid in players.iter() {
+                self.players.insert(*uuid, Arc::downgrade(&info));
+            }
+
+            // The players take up room again once they reconnect.
+            self.games.push(LobbyGame::new(&info, difficulty, room));
+
+            if let Err(err) = Self::start_ai(&info, &ai_players) {
+                eprintln!("Unable to restart the AI of {} : {err}", path.display());
+            }
+        }
+    }
+
     /// Find a suitable game for the JoinMessage, try to reconnect to session if UUID is specified in message.
-    fn find_suitable_game(&mut self, join_message: &JoinMessageBody) -> MatchmakingInfo {
+    /// Matchmaking only picks or creates games with fog of war if `fog_of_war` is set.
+    fn find_suitable_game(
+        &mut self,
+        join_message: &JoinMessageBody,
+        fog_of_war: bool,
+    ) -> MatchmakingInfo {
         match join_message.player_id {
             // Try to reconnect player to session.
             Some(uuid) => match self.get_player_game(&uuid) {
@@ -152,13 +381,15 @@ impl Lobby {
                 None => MatchmakingInfo::ExpiredUuid,
             },
 
-            // Create a new session.
+            // Join the private room.
+            None if join_message.room.is_some() => self.join_room(join_message),
+
+            // Find a matching session, or create a new one.
             None => {
-                // TODO: Find matching session, use proper matchmaking
-                if let Some(session) = self.games.first().and_then(|session| session.upgrade()) {
+                if let Some(session) = self.find_matching_game(join_message, fog_of_war) {
                     MatchmakingInfo::JoinedGame(Uuid::new_v4(), session)
                 } else {
-                    match self.create_new_game(join_message) {
+                    match self.create_new_game(join_message, fog_of_war) {
                         Ok(game) => MatchmakingInfo::JoinedGame(Uuid::new_v4(), game),
                         Err(err) => MatchmakingInfo::InternalFailure(err),
                     }
@@ -167,12 +398,197 @@ impl Lobby {
         }
     }
 
+    /// Find a running game with the same difficulty and fog of war that still has room
+    /// for a player.
+    fn find_matching_game(
+        &self,
+        join_message: &JoinMessageBody,
+        fog_of_war: bool,
+    ) -> Option<Arc<GameSessionInfo>> {
+        self.games
+            .iter()
+            .filter(|entry| {
+                entry.room.is_none()
+                    && entry.difficulty == join_message.difficulty
+                    && entry.player_count() < self.config.max_players
+            })
+            .filter_map(|entry| entry.session.upgrade())
+            .find(|session| session.fog_of_war() == fog_of_war)
+    }
+
+    /// Join the private room specified in the JoinMessage.
+    fn join_room(&self, join_message: &JoinMessageBody) -> MatchmakingInfo {
+        let entry = self
+            .games
+            .iter()
+            .find(|entry| entry.room.is_some() && entry.room == join_message.room);
+
+        match entry.and_then(|entry| Some((entry.session.upgrade()?, entry))) {
+            Some((_, entry)) if entry.player_count() >= self.config.max_players => {
+                MatchmakingInfo::GameFull
+            }
+            Some((session, _)) => MatchmakingInfo::JoinedGame(Uuid::new_v4(), session),
+            None => MatchmakingInfo::UnknownRoom,
+        }
+    }
+
+    /// Create a new private room (with fog of war if `fog_of_war` is set), returns the room code.
+    fn create_room(&mut self, difficulty: u32, fog_of_war: bool) -> Result<Box<str>, ServerError> {
+        // Generate a code that isn't used by another room.
+        let code = loop {
+            let code: Box<str> = (0..ROOM_CODE_LENGTH)
+                .map(|_| ROOM_CODE_CHARSET[self.rng.usize(..ROOM_CODE_CHARSET.len())] as char)
+                .collect();
+
+            if !self
+                .games
+                .iter()
+                .any(|entry| entry.room.as_ref() == Some(&code))
+            {
+                break code;
+            }
+        };
+
+        self.create_new_game(
+            &JoinMessageBody {
+                difficulty,
+                player_id: None,
+                room: Some(code.clone()),
+                pheromon_encoding: Default::default(),
+            },
+            fog_of_war,
+        )?;
+
+        Ok(code)
+    }
+
+    /// Get the path of the record of the game session `id`.
+    fn find_record(&self, id: &Uuid) -> Result<PathBuf, ServerError> {
+        let path = self.config.record_directory.join(format!("{id}.jsonl"));
+
+        if path.is_file() {
+            Ok(path)
+        } else {
+            Err(ServerError::UnknownRecord(*id))
+        }
+    }
+
     fn housekeep(&mut self) {
         // Remove all player UUID that references games that doesn't exist anymore.
         self.players
             .retain(|_, session| session.upgrade().is_some());
 
         // Remove all session references for games that doesn't exist anymore.
-        self.games.retain(|session| session.upgrade().is_some());
+        self.games.retain(|entry| entry.session.upgrade().is_some());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{TcpListener, TcpStream};
+
+    use crate::message::types::{HeartbeatMessageBody, Message};
+
+    use super::*;
+
+    fn join(room: Option<&str>) -> JoinMessageBody {
+        JoinMessageBody {
+            difficulty: 1,
+            player_id: None,
+            room: room.map(Into::into),
+            pheromon_encoding: Default::default(),
+        }
+    }
+
+    #[test]
+    fn leaving_frees_room() {
+        let mut lobby = Lobby::new(LobbyConfig {
+            max_players: 1,
+            seed: Some(0),
+            ..Default::default()
+        });
+
+        let code = lobby.create_room(1, false).unwrap();
+
+        let uuid = match lobby.matchmaking(&join(Some(&code)), &None) {
+            MatchmakingInfo::JoinedGame(uuid, _) => uuid,
+            _ => panic!("unable to join the empty room"),
+        };
+
+        assert!(matches!(
+            lobby.matchmaking(&join(Some(&code)), &None),
+            MatchmakingInfo::GameFull
+        ));
+
+        lobby.leave(&uuid);
+
+        assert!(matches!(
+            lobby.matchmaking(&join(Some(&code)), &None),
+            MatchmakingInfo::JoinedGame(..)
+        ));
+    }
+    #[test]
+    fn matchmaking_skips_fog_of_war() {
+        let mut lobby = Lobby::new(LobbyConfig {
+            game: GameConfig {
+                fog_of_war: Some(Default::default()),
+                ..Default::default()
+            },
+            seed: Some(0),
+            ..Default::default()
+        });
+
+        let mut fog_of_war = |capabilities: Vec<Capability>| match lobby
+            .matchmaking(&join(None), &Some(capabilities))
+        {
+            MatchmakingInfo::JoinedGame(_, session) => session.fog_of_war(),
+            _ => panic!("unable to join a game"),
+        };
+
+        assert!(!fog_of_war(vec![]));
+        assert!(fog_of_war(vec![Capability::FogOfWar]));
+        assert!(!fog_of_war(vec![Capability::Heartbeat]));
+        assert!(fog_of_war(vec![Capability::FogOfWar]));
+
+        // One game of each kind.
+        assert_eq!(lobby.games.len(), 2);
+    }
+    #[test]
+    fn legacy_client_stays_idle() {
+        let heartbeat = HeartbeatConfig {
+            ping_delay: 50,
+            timeout: 100,
+        };
+
+        let mut lobby = Lobby::new(LobbyConfig {
+            heartbeat: Some(heartbeat),
+            seed: Some(0),
+            ..Default::default()
+        });
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        lobby.add_listener(listener);
+        thread::spawn(move || lobby.run());
+
+        // Join without saying hello, then stay idle longer than the heartbeat timeout.
+        let mut client = TcpStream::connect(addr).unwrap();
+
+        client.write_message(&Message::Join(join(None))).unwrap();
+        assert!(matches!(client.read_message(), Ok(Message::OkMaze(_))));
+
+        thread::sleep(Duration::from_millis(3 * heartbeat.timeout));
+
+        client
+            .write_message(&Message::Ping(HeartbeatMessageBody { id: 1 }))
+            .unwrap();
+
+        loop {
+            if let Message::Pong(body) = client.read_message().unwrap() {
+                assert_eq!(body.id, 1);
+                break;
+            }
+        }
     }
 }
@@ -4,13 +4,16 @@ use std::collections::VecDeque;
 
 use fastrand::Rng;
 
-use super::Maze;
+use super::{Maze, Nest};
 use crate::{
     config::{GeneratorConfig, NestPositioning},
     error::ServerError,
     message::types::{JoinMessageBody, MoveDirection},
 };
 
+/// Amount of random proposals tried for each food source before giving up.
+const FOOD_PLACEMENT_ATTEMPTS: u32 = 100;
+
 impl Maze {
     /// Put walls on the maze hulls.
     fn generate_border(&mut self) {
@@ -62,12 +65,19 @@ impl Maze {
         let mut foods = vec![];
         foods.reserve(food_count as usize);
 
-        for _ in 0..food_count {
+        // Retry the rejected proposals, but give up after a while (the maze may be too small).
+        for _ in 0..food_count * FOOD_PLACEMENT_ATTEMPTS {
+            if foods.len() == food_count as usize {
+                break;
+            }
+
             let proposal = (rng.u32(0..self.nb_column), rng.u32(0..self.nb_line));
 
-            /* Check minimum distance and already taken tile. */
-            if (proposal.0 * proposal.0 + proposal.1 * proposal.1) < minimum_distance2
-                || foods.contains(&proposal)
+            /* Check minimum distance to each nest and already taken tile. */
+            if self.nests.iter().any(|nest| {
+                proposal.0.abs_diff(nest.column).pow(2) + proposal.1.abs_diff(nest.line).pow(2)
+                    < minimum_distance2
+            }) || foods.contains(&proposal)
             {
                 continue;
             }
@@ -84,6 +94,68 @@ impl Maze {
         Ok(foods.into_boxed_slice())
     }
 
+    /// Place `nest_count` nests, the first one being at `first_nest`.
+    /// Each other nest is put as far as possible from the already placed ones, so that
+    /// no team is advantaged.
+    fn place_nests(
+        &mut self,
+        first_nest: (u32, u32),
+        nest_count: u32,
+        rng: &Rng,
+    ) -> Result<(), ServerError> {
+        if nest_count == 0 || nest_count > self.nb_column * self.nb_line {
+            return Err(ServerError::invalid_maze(format!(
+                "Can't place {nest_count} nests"
+            )));
+        }
+
+        let mut nests = vec![Nest {
+            column: first_nest.0,
+            line: first_nest.1,
+        }];
+
+        for _ in 1..nest_count {
+            // Manhattan distance to the nearest nest of each free tile.
+            let candidates: Vec<(u32, Nest)> = (0..self.nb_line)
+                .flat_map(|line| (0..self.nb_column).map(move |column| Nest { column, line }))
+                .filter(|tile| !nests.contains(tile))
+                .map(|tile| {
+                    let distance = nests
+                        .iter()
+                        .map(|nest| {
+                            tile.column.abs_diff(nest.column) + tile.line.abs_diff(nest.line)
+                        })
+                        .min()
+                        .unwrap_or_default();
+
+                    (distance, tile)
+                })
+                .collect();
+
+            // Pick randomly among the farthest tiles.
+            let max_distance = candidates.iter().map(|(distance, _)| *distance).max();
+            let farthest: Vec<Nest> = candidates
+                .into_iter()
+                .filter(|(distance, _)| Some(*distance) == max_distance)
+                .map(|(_, tile)| tile)
+                .collect();
+
+            nests.push(farthest[rng.usize(..farthest.len())]);
+        }
+
+        for nest in nests.iter() {
+            if let Some(tile) = self.get_tile_mut(nest.column, nest.line) {
+                tile.set_nest(true);
+            }
+        }
+
+        self.nest_column = first_nest.0;
+        self.nest_line = first_nest.1;
+        self.nests = nests.into_boxed_slice();
+
+        Ok(())
+    }
+
     fn get_random_neighbor(
         &self,
         (column, line): (u32, u32),
@@ -208,15 +280,16 @@ pub fn generate_empty_maze(nb_column: u32, nb_line: u32, rng: &Rng) -> Result<Ma
     let mut maze = Maze {
         nb_column: nb_column.into(),
         nb_line: nb_line.into(),
-        nest_column: fastrand::u32(0..nb_column),
-        nest_line: fastrand::u32(0..nb_line),
         tiles: vec![0u8; (nb_column * nb_line) as usize].into_boxed_slice(),
+        ..Default::default()
     };
 
     // Place the nest
-    if let Some(tile) = maze.get_tile_mut(maze.nest_column, maze.nest_line) {
-        tile.set_nest(true);
-    }
+    maze.place_nests(
+        (fastrand::u32(0..nb_column), fastrand::u32(0..nb_line)),
+        1,
+        rng,
+    )?;
 
     maze.generate_border();
     maze.place_food(1, rng)?;
@@ -227,6 +300,7 @@ pub fn generate_empty_maze(nb_column: u32, nb_line: u32, rng: &Rng) -> Result<Ma
 pub fn generate_maze_backtracking(
     (nb_column, nb_line): (u32, u32),
     (nest_column, nest_line): (u32, u32),
+    nb_nests: u32,
     nb_food: u32,
     carving_amount: u32,
     rng: &Rng,
@@ -238,15 +312,12 @@ pub fn generate_maze_backtracking(
     let mut maze = Maze {
         nb_column,
         nb_line,
-        nest_column,
-        nest_line,
         tiles: vec![0u8; (nb_column * nb_line) as usize].into_boxed_slice(),
+        ..Default::default()
     };
 
-    // Place the nest
-    if let Some(tile) = maze.get_tile_mut(maze.nest_column, maze.nest_line) {
-        tile.set_nest(true);
-    }
+    // Place the nests
+    maze.place_nests((nest_column, nest_line), nb_nests, rng)?;
 
     maze.fill_maze();
     maze.place_food(nb_food, rng)?;
@@ -275,5 +346,12 @@ pub fn generate_maze(
 
     let nb_food = config.nb_food_min + (config.nb_food_coeff * critera.difficulty as f32) as u32;
 
-    generate_maze_backtracking(size, nest_pos, nb_food, config.carving_amount, rng)
+    generate_maze_backtracking(
+        size,
+        nest_pos,
+        config.nb_nests,
+        nb_food,
+        config.carving_amount,
+        rng,
+    )
 }
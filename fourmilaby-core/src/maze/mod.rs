@@ -8,6 +8,7 @@ use std::{
     ops::Not,
 };
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 use crate::{error::ServerError, message::types::MoveDirection};
@@ -23,15 +24,35 @@ impl From<u8> for Tile {
     }
 }
 
+impl From<Tile> for u8 {
+    fn from(tile: Tile) -> Self {
+        tile.0
+    }
+}
+
+/// The position of a nest in a [`Maze`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Nest {
+    pub column: u32,
+    pub line: u32,
+}
+
 /// A maze or "Labyrinth".
-#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct Maze {
     pub nb_column: u32,
     pub nb_line: u32,
+
+    /// Position of the first nest (team 0), see `nests`.
     pub nest_column: u32,
     pub nest_line: u32,
 
+    /// All the nests of the maze, each nest is owned by the team of the same index.
+    #[serde(default)]
+    pub nests: Box<[Nest]>,
+
     /// Array of tiles, where each [`u8`] is an actual tile (that can be wrapped into a [`Tile`]).
     /// Is `nb_column` * `nb_line` large.
     ///
@@ -108,6 +129,15 @@ impl Tile {
         set_bit(&mut self.0, 5, value);
     }
 
+    /// The tile is hidden by the fog of war, its other bits are meaningless.
+    pub fn is_unknown(&self) -> bool {
+        (self.0 & (1 << 6)) > 0
+    }
+
+    pub fn set_unknown(&mut self, value: bool) {
+        set_bit(&mut self.0, 6, value);
+    }
+
     pub fn get_walls(&self) -> Vec<MoveDirection> {
         let mut directions = Vec::with_capacity(4);
 
@@ -162,16 +192,14 @@ impl Maze {
             ));
         }
 
-        // Get the position of each nests.
-        let nests: Vec<(u32, u32)> = tiles
+        // Get the position of each nests, teams follow the tiles ordering.
+        let nests: Box<[Nest]> = tiles
             .iter()
             .enumerate() // get the index (=> position) of each tile
             .filter(|(_, tile)| Tile(**tile).is_nest())
-            .map(|(i, _)| {
-                (
-                    /* x: */ (i as u32) % width,
-                    /* y: */ (i as u32) / width,
-                )
+            .map(|(i, _)| Nest {
+                column: (i as u32) % width,
+                line: (i as u32) / width,
             })
             .collect();
 
@@ -179,21 +207,56 @@ impl Maze {
             return ServerError::invalid_maze_error("No nest found in tiles");
         }
 
-        if nests.len() > 1 {
-            unimplemented!("Consider multiples nest");
-        }
-
-        let nest_pos = nests[0];
-
         Ok(Maze {
             nb_column: width,
             nb_line: height,
-            nest_column: nest_pos.0,
-            nest_line: nest_pos.1,
+            nest_column: nests[0].column,
+            nest_line: nests[0].line,
+            nests,
             tiles: tiles.into(),
         })
     }
 
+    /// Get the nest of a team, falls back to the first nest if the maze doesn't list its nests.
+    pub fn get_nest(&self, team: u32) -> Option<Nest> {
+        if self.nests.is_empty() && team == 0 {
+            return Some(Nest {
+                column: self.nest_column,
+                line: self.nest_line,
+            });
+        }
+
+        self.nests.get(team as usize).copied()
+    }
+
+    /// Get the team owning the nest at (`x`, `y`), if there is one.
+    pub fn get_nest_team(&self, x: u32, y: u32) -> Option<u32> {
+        if self.nests.is_empty() {
+            return (x == self.nest_column && y == self.nest_line).then_some(0);
+        }
+
+        self.nests
+            .iter()
+            .position(|nest| nest.column == x && nest.line == y)
+            .map(|team| team as u32)
+    }
+
+    /// Get a copy of the maze with all its tiles hidden by the fog of war, the nests stay known.
+    pub fn fogged(&self) -> Self {
+        let mut unknown = Tile(0);
+        unknown.set_unknown(true);
+
+        Self {
+            tiles: vec![unknown.0; self.tiles.len()].into_boxed_slice(),
+            ..self.clone()
+        }
+    }
+
+    /// Get the amount of teams (one per nest).
+    pub fn team_count(&self) -> u32 {
+        (self.nests.len() as u32).max(1)
+    }
+
     pub fn get_tile(&self, x: u32, y: u32) -> Option<Tile> {
         if x >= self.nb_column || y >= self.nb_line {
             // Out of bounds
@@ -1,4 +1,6 @@
 //! Message and protocol implementation between client and server.
 
+pub mod pheromon;
+pub mod schema;
 pub mod transmit;
 pub mod types;
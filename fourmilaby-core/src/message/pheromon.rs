@@ -0,0 +1,141 @@
+//! Delta encoding of the pheromon grids of the info messages, see [`PheromonEncoding`].
+use std::sync::Arc;
+
+use super::types::{InfoMessageBody, PheromonDeltaBody, PheromonEncoding, PheromonLayerBody};
+
+/// Quantize a pheromon level to a [`u8`].
+pub fn quantize(level: f32) -> u8 {
+    (level.clamp(0.0, 1.0) * u8::MAX as f32).round() as u8
+}
+
+/// Get back a pheromon level from its quantized value.
+pub fn dequantize(level: u8) -> f32 {
+    level as f32 / u8::MAX as f32
+}
+
+/// Encode the pheromon of the info messages sent to a client.
+#[derive(Default)]
+pub struct PheromonEncoder {
+    encoding: PheromonEncoding,
+
+    /// Grids as last sent to the client (legacy grid first, then the layers),
+    /// empty until the first info message.
+    #[allow(clippy::redundant_allocation)]
+    sent: Vec<Arc<Box<[f32]>>>,
+}
+
+impl PheromonEncoder {
+    pub fn new(encoding: PheromonEncoding) -> Self {
+        Self {
+            encoding,
+            sent: vec![],
+        }
+    }
+
+    /// Replace the pheromon grids of the message by their changes since the last encoded message.
+    /// The first message, and every message in [`PheromonEncoding::Full`], is left untouched.
+    pub fn encode(&mut self, info: &mut InfoMessageBody) {
+        if self.encoding == PheromonEncoding::Full {
+            return;
+        }
+
+        let grids: Vec<_> = std::iter::once(info.pheromon.clone())
+            .chain(
+                info.pheromon_layers
+                    .iter()
+                    .map(|layer| layer.pheromon.clone()),
+            )
+            .collect();
+
+        // Send the whole grids if it's the first message, or if the layers changed.
+        if self.sent.len() != grids.len()
+            || self
+                .sent
+                .iter()
+                .zip(grids.iter())
+                .any(|(sent, grid)| sent.len() != grid.len())
+        {
+            self.sent = grids;
+            return;
+        }
+
+        let quantized = self.encoding == PheromonEncoding::QuantizedDelta;
+
+        info.pheromon_deltas = self
+            .sent
+            .iter()
+            .zip(grids.iter())
+            .enumerate()
+            // Unchanged grids are still shared with the game state (clone-on-write).
+            .filter(|(_, (sent, grid))| !Arc::ptr_eq(sent, grid))
+            .filter_map(|(layer, (sent, grid))| {
+                let indices: Vec<u32> = sent
+                    .iter()
+                    .zip(grid.iter())
+                    .enumerate()
+                    .filter(|(_, (sent, level))| match quantized {
+                        true => quantize(**sent) != quantize(**level),
+                        false => sent != level,
+                    })
+                    .map(|(index, _)| index as u32)
+                    .collect();
+
+                if indices.is_empty() {
+                    return None;
+                }
+
+                let levels = indices.iter().map(|index| grid[*index as usize]);
+
+                Some(PheromonDeltaBody {
+                    layer: layer as u32,
+                    levels: match quantized {
+                        true => vec![],
+                        false => levels.clone().collect(),
+                    },
+                    quantized_levels: match quantized {
+                        true => levels.map(quantize).collect(),
+                        false => vec![],
+                    },
+                    indices,
+                })
+            })
+            .collect();
+
+        info.pheromon = Default::default();
+        info.pheromon_layers.clear();
+
+        self.sent = grids;
+    }
+}
+
+/// Apply the [`InfoMessageBody::pheromon_deltas`] to the grids received previously.
+#[allow(clippy::redundant_allocation)]
+pub fn apply_deltas(
+    deltas: &[PheromonDeltaBody],
+    pheromon: &mut Arc<Box<[f32]>>,
+    layers: &mut [PheromonLayerBody],
+) {
+    for delta in deltas {
+        let grid = match delta.layer {
+            0 => &mut *pheromon,
+            layer => match layers.get_mut(layer as usize - 1) {
+                Some(layer) => &mut layer.pheromon,
+                None => continue,
+            },
+        };
+
+        let grid = Arc::make_mut(grid);
+
+        let levels = delta
+            .levels
+            .iter()
+            .copied()
+            .chain(delta.quantized_levels.iter().copied().map(dequantize));
+
+        for (index, level) in delta.indices.iter().zip(levels) {
+            if let Some(cell) = grid.get_mut(*index as usize) {
+                *cell = level;
+            }
+        }
+    }
+}
@@ -0,0 +1,164 @@
+//! JSON Schema and TypeScript definitions of the protocol, generated from [`Message`].
+//!
+//! The TypeScript definitions are derived from the JSON Schema, so both always describe
+//! the same protocol. See the `schema` subcommand of the server.
+use schemars::{gen::SchemaSettings, schema::RootSchema};
+use serde_json::Value;
+
+use crate::error::{CapabilityDetails, RecordDetails, VersionDetails};
+
+use super::types::Message;
+
+/// Get the JSON Schema of the [`Message`]s, with all the types they use as definitions.
+pub fn protocol_schema() -> RootSchema {
+    let mut gen = SchemaSettings::draft07().into_generator();
+
+    // Not referenced by the messages (see `ErrorBody::details`), but part of the protocol.
+    gen.subschema_for::<VersionDetails>();
+    gen.subschema_for::<RecordDetails>();
+    gen.subschema_for::<CapabilityDetails>();
+
+    gen.into_root_schema_for::<Message>()
+}
+
+/// Get the TypeScript definitions of the types of a JSON Schema (e.g. [`protocol_schema`]),
+/// one exported type per definition.
+pub fn typescript(schema: &RootSchema) -> String {
+    let schema = serde_json::to_value(schema).unwrap_or_default();
+    let mut output = String::from("// Generated from the protocol JSON Schema, do not edit.\n");
+
+    let definitions = schema["definitions"]
+        .as_object()
+        .cloned()
+        .unwrap_or_default();
+
+    // The root schema is usually also a definition (e.g. if it's recursive).
+    let root = schema["title"]
+        .as_str()
+        .filter(|title| !definitions.contains_key(*title))
+        .map(|title| (title.to_string(), schema.clone()));
+
+    for (name, definition) in definitions.into_iter().chain(root) {
+        output.push('\n');
+        output.push_str(&doc_comment(&definition, ""));
+
+        match definition.get("properties") {
+            Some(_) => output.push_str(&format!(
+                "export interface {name} {}\n",
+                ts_type(&definition, "")
+            )),
+            None => output.push_str(&format!(
+                "export type {name} = {};\n",
+                ts_type(&definition, "")
+            )),
+        }
+    }
+
+    output
+}
+
+/// Get the JSDoc comment of a schema from its description, empty if there is none.
+fn doc_comment(schema: &Value, indent: &str) -> String {
+    match schema["description"].as_str() {
+        Some(description) => {
+            let lines: String = description
+                .lines()
+                .map(|line| format!("{indent} * {line}").trim_end().to_string() + "\n")
+                .collect();
+
+            format!("{indent}/**\n{lines}{indent} */\n")
+        }
+        None => String::new(),
+    }
+}
+
+/// Get the TypeScript type of a schema, `indent` being the indentation of the enclosing type.
+fn ts_type(schema: &Value, indent: &str) -> String {
+    if let Some(reference) = schema["$ref"].as_str() {
+        return reference
+            .rsplit('/')
+            .next()
+            .unwrap_or(reference)
+            .to_string();
+    }
+
+    if let Some(values) = schema["enum"].as_array() {
+        return union(values.iter().map(Value::to_string));
+    }
+
+    for key in ["oneOf", "anyOf", "allOf"] {
+        if let Some(schemas) = schema[key].as_array() {
+            let types = schemas.iter().map(|schema| ts_type(schema, indent));
+
+            return match key {
+                "allOf" => types.collect::<Vec<_>>().join(" & "),
+                _ => union(types),
+            };
+        }
+    }
+
+    match &schema["type"] {
+        Value::String(instance_type) => instance_ts_type(instance_type, schema, indent),
+        Value::Array(instance_types) => union(
+            instance_types
+                .iter()
+                .filter_map(Value::as_str)
+                .map(|instance_type| instance_ts_type(instance_type, schema, indent)),
+        ),
+        // Any JSON value.
+        _ => "unknown".into(),
+    }
+}
+
+/// Get the TypeScript type of a JSON type (e.g. `integer`) of a schema.
+fn instance_ts_type(instance_type: &str, schema: &Value, indent: &str) -> String {
+    match instance_type {
+        "integer" | "number" => "number".into(),
+        "string" => "string".into(),
+        "boolean" => "boolean".into(),
+        "null" => "null".into(),
+        "array" => match ts_type(&schema["items"], indent) {
+            items if items.contains(' ') => format!("({items})[]"),
+            items => format!("{items}[]"),
+        },
+        "object" => match schema["properties"].as_object() {
+            Some(properties) => {
+                let inner = format!("{indent}  ");
+                let required = schema["required"].as_array();
+
+                let fields: String = properties
+                    .iter()
+                    .map(|(name, property)| {
+                        let optional = match required {
+                            Some(required) if required.iter().any(|field| field == name) => "",
+                            _ => "?",
+                        };
+
+                        format!(
+                            "{}{inner}{name}{optional}: {};\n",
+                            doc_comment(property, &inner),
+                            ts_type(property, &inner)
+                        )
+                    })
+                    .collect();
+
+                format!("{{\n{fields}{indent}}}")
+            }
+            None => "Record<string, unknown>".into(),
+        },
+        _ => "unknown".into(),
+    }
+}
+
+/// Join TypeScript types into a union, without duplicates.
+fn union(types: impl Iterator<Item = String>) -> String {
+    let mut members: Vec<String> = vec![];
+
+    for member in types {
+        if !members.contains(&member) {
+            members.push(member);
+        }
+    }
+
+    members.join(" | ")
+}
@@ -1,8 +1,12 @@
 //! # Transmission primitives
 //!
 //! Transmission currently works using a [`u32`] big-endian number that corresponds to
-//! the size of the payload message, followed by this payload message that is a JSON
-//! [`super::types::Message`] object.
+//! the size of the payload message, followed by this payload message that is a
+//! [`super::types::Message`] object, encoded as per [`WireEncoding`].
+//!
+//! The encoding of a payload is detected when reading it, so that the server answers each
+//! client using the encoding of its first message
+//! (see [`crate::protocols::encoded::EncodedChannel`]).
 //!
 use std::io::{Read, Write};
 
@@ -11,9 +15,56 @@ use crate::error::ServerError;
 
 const MAX_MESSAGE_SIZE: u32 = 4 * 1024 * 1024; // 4 MiB
 
-/// Write a [Message] to `writer` using the protocol.
+/// Encoding of the payload messages.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WireEncoding {
+    /// JSON object (the original encoding).
+    #[default]
+    Json,
+    /// MessagePack map, the structures are encoded as maps using the JSON field names.
+    MessagePack,
+}
+
+impl WireEncoding {
+    /// Detect the encoding of a payload : JSON objects start with `{`, MessagePack maps don't.
+    pub fn detect(data: &[u8]) -> Self {
+        match data.iter().find(|byte| !byte.is_ascii_whitespace()) {
+            Some(b'{') | None => WireEncoding::Json,
+            Some(_) => WireEncoding::MessagePack,
+        }
+    }
+
+    /// Encode a [Message] as a payload.
+    pub fn encode(self, message: &Message) -> Result<Vec<u8>, ServerError> {
+        match self {
+            WireEncoding::Json => Ok(serde_json::to_vec(message)?),
+            WireEncoding::MessagePack => Ok(rmp_serde::to_vec_named(message)?),
+        }
+    }
+
+    /// Decode a [Message] from a payload.
+    pub fn decode(self, data: &[u8]) -> Result<Message, ServerError> {
+        match self {
+            WireEncoding::Json => serde_json::from_slice(data)
+                .map_err(|err| ServerError::BadMessage(err.to_string().into())),
+            WireEncoding::MessagePack => rmp_serde::from_slice(data)
+                .map_err(|err| ServerError::BadMessage(err.to_string().into())),
+        }
+    }
+}
+
+/// Write a [Message] to `writer` using the protocol, encoded as JSON.
 pub fn write_message<W: Write>(writer: &mut W, message: &Message) -> Result<(), ServerError> {
-    write_message_raw(writer, serde_json::to_string(message)?.as_bytes())
+    write_message_encoded(writer, message, WireEncoding::Json)
+}
+
+/// Write a [Message] to `writer` using the protocol and the given [`WireEncoding`].
+pub fn write_message_encoded<W: Write>(
+    writer: &mut W,
+    message: &Message,
+    encoding: WireEncoding,
+) -> Result<(), ServerError> {
+    write_message_raw(writer, &encoding.encode(message)?)
 }
 
 /// Write raw data to `writer` using the protocol.
@@ -57,9 +108,15 @@ pub fn read_message_raw<R: Read>(reader: &mut R) -> Result<Box<[u8]>, ServerErro
 
 /// Read a [Message] from `reader` using the protocol.
 pub fn read_message<R: Read>(reader: &mut R) -> Result<Message, ServerError> {
-    let data = read_message_raw(reader)?;
+    read_message_encoded(reader).map(|(message, _)| message)
+}
 
-    let message = serde_json::from_slice::<Message>(&data)?;
+/// Read a [Message] from `reader` using the protocol, along with its detected [`WireEncoding`].
+pub fn read_message_encoded<R: Read>(
+    reader: &mut R,
+) -> Result<(Message, WireEncoding), ServerError> {
+    let data = read_message_raw(reader)?;
+    let encoding = WireEncoding::detect(&data);
 
-    Ok(message)
+    Ok((encoding.decode(&data)?, encoding))
 }
@@ -1,11 +1,32 @@
 //! Message structures.
 use std::sync::Arc;
 
+use schemars::{
+    gen::SchemaGenerator,
+    schema::{InstanceType, Metadata, Schema, SchemaObject},
+    JsonSchema,
+};
 use serde::{Deserialize, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
 
 use crate::{error::ServerError, maze::Maze};
 
+/// Version of the protocol, bumped on every incompatible change of the messages.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Oldest protocol version still supported.
+pub const MIN_PROTOCOL_VERSION: u32 = 2;
+
+/// Capabilities supported by this implementation, for both the server and the client.
+pub const SUPPORTED_CAPABILITIES: &[Capability] = &[
+    Capability::MessagePack,
+    Capability::PheromonDeltas,
+    Capability::FogOfWar,
+    Capability::Rooms,
+    Capability::Replays,
+    Capability::Heartbeat,
+];
+
 /// Each movement a player can do.
 #[derive(Debug, Clone, Copy, Serialize_repr, Deserialize_repr)]
 #[repr(u32)]
@@ -30,6 +51,28 @@ impl MoveDirection {
     }
 }
 
+// Serialized as its discriminant, which the derive doesn't know about.
+impl JsonSchema for MoveDirection {
+    fn schema_name() -> String {
+        "MoveDirection".into()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        SchemaObject {
+            metadata: Some(Box::new(Metadata {
+                description: Some(
+                    "Each movement a player can do : 0 North, 1 South, 2 East, 3 West.".into(),
+                ),
+                ..Default::default()
+            })),
+            instance_type: Some(InstanceType::Integer.into()),
+            enum_values: Some((0..=3).map(serde_json::Value::from).collect()),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
 impl Into<(i32, i32)> for MoveDirection {
     fn into(self) -> (i32, i32) {
         match self {
@@ -41,8 +84,61 @@ impl Into<(i32, i32)> for MoveDirection {
     }
 }
 
+/// Optional feature of the protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum Capability {
+    /// MessagePack encoded messages, see [`crate::message::transmit::WireEncoding`].
+    MessagePack,
+    /// Delta encoded pheromon, see [`PheromonEncoding`].
+    PheromonDeltas,
+    /// Perceived tiles in the info messages, see [`InfoMessageBody::tiles`].
+    FogOfWar,
+    /// Private rooms, see [`Message::CreateRoom`].
+    Rooms,
+    /// Game replays, see [`Message::WatchReplay`].
+    Replays,
+    /// Keep-alive pings, see [`Message::Ping`].
+    Heartbeat,
+    /// A capability unknown to this implementation (e.g. from a newer version).
+    #[serde(other)]
+    Unknown,
+}
+
+/// Message exchanged when connecting, before any other message : the client sends its
+/// protocol version and capabilities, the server answers with its own version and the
+/// capabilities both support, or with an error if the client is incompatible.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct HelloMessageBody {
+    pub version: u32,
+    #[serde(default)]
+    pub capabilities: Vec<Capability>,
+}
+
+impl HelloMessageBody {
+    /// Hello of this implementation.
+    pub fn new() -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            capabilities: SUPPORTED_CAPABILITIES.to_vec(),
+        }
+    }
+
+    /// Check if the capability got negotiated.
+    pub fn supports(&self, capability: Capability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+}
+
+impl Default for HelloMessageBody {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// Message received by the server by the client in the lobby to initiate the matchmaking.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct JoinMessageBody {
     /// Asked difficulty.
@@ -50,18 +146,108 @@ pub struct JoinMessageBody {
 
     /// Optional player UUID for reconnecting session.
     pub player_id: Option<uuid::Uuid>,
+
+    /// Optional room code, to join a private room (see [`Message::CreateRoom`]).
+    #[serde(default)]
+    pub room: Option<Box<str>>,
+
+    /// How the client wants to receive the pheromon, see [`OkMazeMessageBody::pheromon_encoding`].
+    #[serde(default)]
+    pub pheromon_encoding: PheromonEncoding,
+}
+
+/// How the pheromon grids are sent in the info messages.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum PheromonEncoding {
+    /// The whole grids in each info message.
+    #[default]
+    Full,
+    /// The whole grids in the first info message, then only the changed levels
+    /// (see [`InfoMessageBody::pheromon_deltas`]).
+    Delta,
+    /// Same as `Delta`, with the changed levels quantized to [`u8`] (`0..=255` for `0.0..=1.0`).
+    QuantizedDelta,
+}
+
+/// Message received by the server by the client in the lobby to create a private room.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateRoomMessageBody {
+    /// Asked difficulty.
+    pub difficulty: u32,
+}
+
+/// Message sent by the server to the client in the lobby once the private room is created.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RoomCreatedMessageBody {
+    /// Code to give to [`JoinMessageBody::room`] to join the room.
+    pub code: Box<str>,
 }
 
 /// Message sent by the server to the client in the lobby to prepare the client to join the game session.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct OkMazeMessageBody {
     pub maze: Maze,
     pub player_id: uuid::Uuid,
+
+    /// Pheromon encoding accepted by the server (`full` for the servers without delta encoding).
+    #[serde(default)]
+    pub pheromon_encoding: PheromonEncoding,
+}
+
+/// A named pheromon layer, the grid follows the [`Maze::tiles`] ordering.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PheromonLayerBody {
+    pub name: Box<str>,
+    #[allow(clippy::redundant_allocation)]
+    pub pheromon: Arc<Box<[f32]>>,
+}
+
+/// Changed levels of a pheromon grid since the previous info message.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PheromonDeltaBody {
+    /// Changed grid : `0` for [`InfoMessageBody::pheromon`], `n` for `pheromon_layers[n - 1]`.
+    pub layer: u32,
+    /// Indices of the changed levels, see [`Maze::tiles`].
+    pub indices: Vec<u32>,
+
+    /// New levels, follows `indices` ([`PheromonEncoding::Delta`]).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub levels: Vec<f32>,
+    /// New quantized levels, follows `indices` ([`PheromonEncoding::QuantizedDelta`]).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub quantized_levels: Vec<u8>,
+}
+
+/// Another ant seen by the player.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AntBody {
+    /// Public identifier of the ant, the player UUIDs are never disclosed.
+    pub id: u32,
+    pub team: u32,
+    pub column: u32,
+    pub line: u32,
+    pub has_food: bool,
+}
+
+/// A tile perceived by the player (fog of war), to put in its [`Maze`].
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TileBody {
+    pub column: u32,
+    pub line: u32,
+    /// The tile, see [`Maze::tiles`].
+    pub tile: u8,
 }
 
 /// Message sent by the server to the client that contains the current game view of the player.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct InfoMessageBody {
     pub player_column: u32,
@@ -69,24 +255,191 @@ pub struct InfoMessageBody {
     pub player_has_food: bool,
     #[allow(clippy::redundant_allocation)]
     // Needs to be boxed for Arc::make_mut() (makes [f32] Clone).
+    // Empty when only the deltas are sent, see [`PheromonEncoding`].
     pub pheromon: Arc<Box<[f32]>>,
+
+    /// Additional pheromon layers (`pheromon` being the `food` layer).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pheromon_layers: Vec<PheromonLayerBody>,
+
+    /// Changes of the pheromon grids received previously, when `pheromon` is empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pheromon_deltas: Vec<PheromonDeltaBody>,
+
+    /// Amount of food the player brought back to the nest.
+    #[serde(default)]
+    pub player_food_delivered: u32,
+
+    /// Amount of food brought back to the nest by all the players of the game.
+    #[serde(default)]
+    pub total_food_delivered: u32,
+
+    /// Team of the player, the nest of the team is [`Maze::nests`]`[player_team]`.
+    #[serde(default)]
+    pub player_team: u32,
+
+    /// Amount of food brought back to the nest by the team of the player.
+    #[serde(default)]
+    pub team_food_delivered: u32,
+
+    /// Other ants visible by the player, depends on [`crate::config::GameConfig::ant_visibility`].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ants: Vec<AntBody>,
+
+    /// With the fog of war, the tiles currently perceived by the player (all the tiles it
+    /// revealed in the first message after joining), the pheromon of the other tiles is `0`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tiles: Vec<TileBody>,
+}
+
+/// Message sent by the server to the clients when a food source is depleted,
+/// the tile must not be considered as food anymore.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct FoodDepletedMessageBody {
+    pub column: u32,
+    pub line: u32,
+}
+
+/// Why a game ended.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum GameOverReason {
+    TimeLimit,
+    FoodGoal,
+    FoodDepleted,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// The final score of a player.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PlayerStanding {
+    /// Rank of the player, starting at 1 (players with the same score share the same rank).
+    pub rank: u32,
+    pub team: u32,
+    pub food_delivered: u32,
+}
+
+/// The final score of a team.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TeamStanding {
+    /// Rank of the team, starting at 1 (teams with the same score share the same rank).
+    pub rank: u32,
+    pub team: u32,
+    pub food_delivered: u32,
+}
+
+/// Message sent by the server to the client when the game ends, the game session
+/// is closed right after.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GameOverMessageBody {
+    pub reason: GameOverReason,
+
+    /// Rank of the player receiving this message.
+    pub player_rank: u32,
+    pub player_team: u32,
+    pub total_food_delivered: u32,
+
+    /// Standings of all the players, from the best to the worst.
+    pub standings: Vec<PlayerStanding>,
+
+    /// Standings of all the teams, from the best to the worst.
+    pub team_standings: Vec<TeamStanding>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct MoveMessageBody {
     pub direction: MoveDirection,
 }
 
+/// Message sent by the client to watch the replay of a recorded game.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct WatchReplayMessageBody {
+    /// UUID of the recorded game session.
+    pub id: uuid::Uuid,
+
+    /// Initial playback speed (from 0.5 to 16), 1 if not specified.
+    #[serde(default)]
+    pub speed: Option<f32>,
+}
+
+/// Message sent by a replay watcher to control the playback, absent fields are left unchanged.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayControlMessageBody {
+    /// Playback speed (from 0.5 to 16).
+    #[serde(default)]
+    pub speed: Option<f32>,
+
+    #[serde(default)]
+    pub paused: Option<bool>,
+
+    /// Time to seek to (in milliseconds since the start of the game).
+    #[serde(default)]
+    pub seek: Option<u64>,
+}
+
+/// A player in a [`ReplayFrameMessageBody`].
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayPlayerBody {
+    pub player_column: u32,
+    pub player_line: u32,
+    pub player_has_food: bool,
+    pub player_team: u32,
+    pub player_food_delivered: u32,
+}
+
+/// Message sent by the server to a replay watcher, the state of the replayed game at `time`.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayFrameMessageBody {
+    /// Current time of the replay (in milliseconds since the start of the game).
+    pub time: u64,
+    /// Duration of the replay (in milliseconds).
+    pub duration: u64,
+    pub speed: f32,
+    pub paused: bool,
+
+    pub players: Vec<ReplayPlayerBody>,
+    #[allow(clippy::redundant_allocation)]
+    pub pheromon: Arc<Box<[f32]>>,
+    pub total_food_delivered: u32,
+}
+
+/// Keep-alive message, a [`Message::Ping`] is answered by a [`Message::Pong`] with the same `id`.
+///
+/// The server pings the clients that negotiated [`Capability::Heartbeat`] during the game,
+/// and disconnects the ones that stay silent for too long.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct HeartbeatMessageBody {
+    pub id: u64,
+}
+
 /// Enumeration of all the possible messages formats.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "type", content = "body", rename_all = "camelCase")]
 pub enum Message {
+    Hello(HelloMessageBody),
     Join(JoinMessageBody),
     OkMaze(OkMazeMessageBody),
     Info(InfoMessageBody),
     Error(ServerError),
     Move(MoveMessageBody),
+    CreateRoom(CreateRoomMessageBody),
+    RoomCreated(RoomCreatedMessageBody),
+    FoodDepleted(FoodDepletedMessageBody),
+    GameOver(GameOverMessageBody),
+    WatchReplay(WatchReplayMessageBody),
+    ReplayControl(ReplayControlMessageBody),
+    ReplayFrame(ReplayFrameMessageBody),
+    Ping(HeartbeatMessageBody),
+    Pong(HeartbeatMessageBody),
     Unexpected {
         expected: Vec<Box<str>>,
         received: Box<Message>,
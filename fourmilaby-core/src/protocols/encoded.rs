@@ -0,0 +1,68 @@
+//! Per connection [`WireEncoding`].
+use std::{borrow::Cow, time::Duration};
+
+use crate::{
+    error::ServerError,
+    message::{transmit::WireEncoding, types::Message},
+};
+
+use super::PlayerChannel;
+
+/// A [`PlayerChannel`] sending its messages using a fixed [`WireEncoding`],
+/// e.g. the encoding of the first message of a client.
+pub struct EncodedChannel<C: PlayerChannel> {
+    channel: C,
+    encoding: WireEncoding,
+}
+
+impl<C: PlayerChannel> EncodedChannel<C> {
+    pub fn new(channel: C, encoding: WireEncoding) -> Self {
+        Self { channel, encoding }
+    }
+
+    pub fn encoding(&self) -> WireEncoding {
+        self.encoding
+    }
+
+    /// Change the encoding of the messages written from now on.
+    pub fn set_encoding(&mut self, encoding: WireEncoding) {
+        self.encoding = encoding;
+    }
+}
+
+impl<C: PlayerChannel> PlayerChannel for EncodedChannel<C> {
+    fn read_message_encoded(&mut self) -> Result<(Message, WireEncoding), ServerError> {
+        self.channel.read_message_encoded()
+    }
+
+    fn write_message_encoded(
+        &mut self,
+        message: &Message,
+        encoding: WireEncoding,
+    ) -> Result<(), ServerError> {
+        self.channel.write_message_encoded(message, encoding)
+    }
+
+    fn write_message(&mut self, message: &Message) -> Result<(), ServerError> {
+        self.channel.write_message_encoded(message, self.encoding)
+    }
+
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> Result<(), ServerError> {
+        self.channel.set_read_timeout(timeout)
+    }
+
+    fn stop(&mut self) -> Result<(), ServerError> {
+        self.channel.stop()
+    }
+
+    fn clone_instance(&self) -> Self {
+        Self {
+            channel: self.channel.clone_instance(),
+            encoding: self.encoding,
+        }
+    }
+
+    fn get_name(&self) -> Option<Cow<'_, str>> {
+        self.channel.get_name()
+    }
+}
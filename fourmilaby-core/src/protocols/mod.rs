@@ -1,10 +1,16 @@
 //! Traits for protocol implementation.
+pub mod encoded;
 pub mod tcp;
 pub mod tungstenite;
+#[cfg(unix)]
+pub mod unix;
 
-use std::borrow::Cow;
+use std::{borrow::Cow, time::Duration};
 
-use crate::{error::ServerError, message::types::Message};
+use crate::{
+    error::ServerError,
+    message::{transmit::WireEncoding, types::Message},
+};
 
 /// Something that can accept client instances.
 pub trait LobbyListener<C>: Send + 'static {
@@ -20,17 +26,41 @@ pub trait LobbyListener<C>: Send + 'static {
 
 /// A channel to some client or server.
 pub trait PlayerChannel: Send + 'static {
-    /// Send a message to the remote instance.
+    /// Receive a message from the remote instance, along with its [`WireEncoding`].
+    ///
+    /// #### Note
+    /// See [`crate::message::transmit::read_message_encoded`]
+    fn read_message_encoded(&mut self) -> Result<(Message, WireEncoding), ServerError>;
+
+    /// Send a message to the remote instance using `encoding`.
+    ///
+    /// #### Note
+    /// See [`crate::message::transmit::write_message_encoded`]
+    fn write_message_encoded(
+        &mut self,
+        message: &Message,
+        encoding: WireEncoding,
+    ) -> Result<(), ServerError>;
+
+    /// Receive a message from the remote instance.
     ///
     /// #### Note
     /// See [`crate::message::transmit::read_message`]
-    fn read_message(&mut self) -> Result<Message, ServerError>;
+    fn read_message(&mut self) -> Result<Message, ServerError> {
+        self.read_message_encoded().map(|(message, _)| message)
+    }
 
     /// Send a message to the remote instance.
     ///
     /// #### Note
     /// See [`crate::message::transmit::write_message`]
-    fn write_message(&mut self, message: &Message) -> Result<(), ServerError>;
+    fn write_message(&mut self, message: &Message) -> Result<(), ServerError> {
+        self.write_message_encoded(message, WireEncoding::Json)
+    }
+
+    /// Make the reads fail once nothing has been received for `timeout`, `None` to block
+    /// indefinitely. Shared by the duplicated handles (see [`PlayerChannel::clone_instance`]).
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> Result<(), ServerError>;
 
     /// Gracefully stop the instance.
     fn stop(&mut self) -> Result<(), ServerError>;
@@ -2,22 +2,34 @@
 use std::{
     borrow::Cow,
     net::{Shutdown, TcpListener, TcpStream},
+    time::Duration,
 };
 
 use crate::{
     error::ServerError,
-    message::{transmit, types::Message},
+    message::{
+        transmit::{self, WireEncoding},
+        types::Message,
+    },
 };
 
 use super::{LobbyListener, PlayerChannel};
 
 impl PlayerChannel for TcpStream {
-    fn read_message(&mut self) -> Result<Message, ServerError> {
-        transmit::read_message(self)
+    fn read_message_encoded(&mut self) -> Result<(Message, WireEncoding), ServerError> {
+        transmit::read_message_encoded(self)
     }
 
-    fn write_message(&mut self, message: &Message) -> Result<(), ServerError> {
-        transmit::write_message(self, message)
+    fn write_message_encoded(
+        &mut self,
+        message: &Message,
+        encoding: WireEncoding,
+    ) -> Result<(), ServerError> {
+        transmit::write_message_encoded(self, message, encoding)
+    }
+
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> Result<(), ServerError> {
+        Ok(TcpStream::set_read_timeout(self, timeout)?)
     }
 
     fn stop(&mut self) -> Result<(), ServerError> {
@@ -1 +1,233 @@
 //! Tungstenite-based implementation (websocket).
+//!
+//! Messages are the same [`Message`] objects as the TCP protocol, but each one is carried
+//! by a single websocket frame instead of using the [`u32`] length prefix : a text frame
+//! for JSON, a binary frame for the other [`WireEncoding`]s.
+//!
+//! All the handles of a connection (see [`PlayerChannel::clone_instance`]) share the same
+//! [`WebSocket`], so that the control frames answered while reading never interleave with
+//! the frames being written.
+use std::{
+    borrow::Cow,
+    io::ErrorKind,
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tungstenite::WebSocket;
+
+use crate::{
+    error::ServerError,
+    message::{transmit::WireEncoding, types::Message},
+};
+
+use super::{LobbyListener, PlayerChannel};
+
+/// Delay given to a client to send its websocket handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// State of the websocket of a connection.
+enum WebSocketState {
+    /// Accepted by the listener, the server handshake is done on the first use.
+    Handshake(TcpStream),
+    Open(Box<WebSocket<TcpStream>>),
+    /// The handshake failed.
+    Failed,
+}
+
+/// A connection, shared by all the handles.
+struct Connection {
+    socket: Mutex<WebSocketState>,
+
+    /// Duplicate of the underlying stream, to wait for incoming data without locking `socket`.
+    stream: TcpStream,
+
+    /// See [`PlayerChannel::set_read_timeout`].
+    read_timeout: Mutex<Option<Duration>>,
+}
+
+impl Connection {
+    /// Use the websocket, doing the server handshake first if it's still pending.
+    fn with_socket<R>(
+        &self,
+        f: impl FnOnce(&mut WebSocket<TcpStream>) -> Result<R, ServerError>,
+    ) -> Result<R, ServerError> {
+        let mut state = self.socket.lock()?;
+
+        // Stays failed if the handshake fails.
+        *state = match std::mem::replace(&mut *state, WebSocketState::Failed) {
+            WebSocketState::Handshake(stream) => {
+                WebSocketState::Open(Box::new(Self::handshake(stream)?))
+            }
+            state => state,
+        };
+
+        match &mut *state {
+            WebSocketState::Open(socket) => f(socket),
+            _ => ServerError::transmission_error("Websocket handshake failed"),
+        }
+    }
+
+    /// Do the server handshake, the client must send it within [`HANDSHAKE_TIMEOUT`].
+    fn handshake(stream: TcpStream) -> Result<WebSocket<TcpStream>, ServerError> {
+        stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+
+        let socket = tungstenite::accept(stream).map_err(|err| {
+            ServerError::transmission(format!("Websocket handshake failed : {err}"))
+        })?;
+
+        socket.get_ref().set_read_timeout(None)?;
+
+        Ok(socket)
+    }
+
+    /// Wait for incoming data until `deadline` (if any).
+    fn wait_readable(&self, deadline: Option<Instant>) -> Result<(), ServerError> {
+        let timeout = match deadline {
+            Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                Some(remaining) if !remaining.is_zero() => Some(remaining),
+                _ => return Err(ServerError::TimedOut),
+            },
+            None => None,
+        };
+
+        self.stream.set_read_timeout(timeout)?;
+
+        // The end of the stream is reported by the next read.
+        self.stream.peek(&mut [0])?;
+
+        Ok(())
+    }
+}
+
+/// A websocket connection to some client or server.
+pub struct WebSocketChannel(Arc<Connection>);
+
+impl WebSocketChannel {
+    fn new(state: WebSocketState, stream: TcpStream) -> Self {
+        Self(Arc::new(Connection {
+            socket: Mutex::new(state),
+            stream,
+            read_timeout: Mutex::new(None),
+        }))
+    }
+
+    /// Connect to a websocket server at `addr`.
+    pub fn connect(addr: SocketAddr) -> Result<Self, ServerError> {
+        let stream = TcpStream::connect(addr)?;
+        let duplicate = stream.try_clone()?;
+
+        let (socket, _) = tungstenite::client(format!("ws://{addr}/"), stream)
+            .map_err(|err| ServerError::transmission(err.to_string()))?;
+
+        Ok(Self::new(WebSocketState::Open(Box::new(socket)), duplicate))
+    }
+}
+
+impl PlayerChannel for WebSocketChannel {
+    fn read_message_encoded(&mut self) -> Result<(Message, WireEncoding), ServerError> {
+        let deadline = self
+            .0
+            .read_timeout
+            .lock()?
+            .map(|timeout| Instant::now() + timeout);
+
+        loop {
+            // Only read what already arrived, the writers must not wait for the remote.
+            let frame = self.0.with_socket(|socket| {
+                socket.get_ref().set_nonblocking(true)?;
+                let frame = socket.read();
+                socket.get_ref().set_nonblocking(false)?;
+
+                Ok(frame)
+            })?;
+
+            let data = match frame {
+                Ok(tungstenite::Message::Text(text)) => text.into_bytes(),
+                Ok(tungstenite::Message::Binary(data)) => data,
+                Ok(tungstenite::Message::Close(_)) => {
+                    return ServerError::transmission_error("Websocket closed by remote")
+                }
+                // Control frames are handled by tungstenite.
+                Ok(_) => continue,
+                Err(tungstenite::Error::Io(err)) if err.kind() == ErrorKind::WouldBlock => {
+                    self.0.wait_readable(deadline)?;
+                    continue;
+                }
+                Err(err) => return Err(err.into()),
+            };
+
+            let encoding = WireEncoding::detect(&data);
+
+            return Ok((encoding.decode(&data)?, encoding));
+        }
+    }
+
+    fn write_message_encoded(
+        &mut self,
+        message: &Message,
+        encoding: WireEncoding,
+    ) -> Result<(), ServerError> {
+        let frame = match encoding {
+            WireEncoding::Json => tungstenite::Message::Text(serde_json::to_string(message)?),
+            _ => tungstenite::Message::Binary(encoding.encode(message)?),
+        };
+
+        self.0.with_socket(|socket| Ok(socket.send(frame)?))
+    }
+
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> Result<(), ServerError> {
+        *self.0.read_timeout.lock()? = timeout;
+
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<(), ServerError> {
+        // Try to close the websocket properly, but always shutdown the underlying stream.
+        if let WebSocketState::Open(socket) = &mut *self.0.socket.lock()? {
+            socket.close(None).ok();
+            socket.flush().ok();
+        }
+
+        self.0
+            .stream
+            .shutdown(Shutdown::Both)
+            .map_err(|err| ServerError::Other(err.to_string().into()))
+    }
+
+    fn clone_instance(&self) -> Self {
+        Self(self.0.clone())
+    }
+
+    fn get_name(&self) -> Option<Cow<'_, str>> {
+        self.0
+            .stream
+            .peer_addr()
+            .map(|addr| addr.to_string().into())
+            .ok()
+    }
+}
+
+/// A [`TcpListener`] that accept websocket clients.
+pub struct WebSocketListener(pub TcpListener);
+
+impl LobbyListener<WebSocketChannel> for WebSocketListener {
+    fn accept_client(&mut self) -> Result<(WebSocketChannel, Cow<'_, str>), ServerError> {
+        let (stream, addr) = self.0.accept()?;
+        let duplicate = stream.try_clone()?;
+
+        // The handshake is done by the client session, a slow client only delays itself.
+        Ok((
+            WebSocketChannel::new(WebSocketState::Handshake(stream), duplicate),
+            addr.to_string().into(),
+        ))
+    }
+
+    fn get_binding_name(&self) -> Option<Cow<'_, str>> {
+        self.0
+            .local_addr()
+            .map(|addr| format!("ws://{addr}").into())
+            .ok()
+    }
+}
@@ -0,0 +1,72 @@
+//! Unix domain socket implementation, using the same transmission as the TCP protocol.
+use std::{
+    borrow::Cow,
+    net::Shutdown,
+    os::{
+        fd::AsRawFd,
+        unix::net::{UnixListener, UnixStream},
+    },
+    time::Duration,
+};
+
+use crate::{
+    error::ServerError,
+    message::{
+        transmit::{self, WireEncoding},
+        types::Message,
+    },
+};
+
+use super::{LobbyListener, PlayerChannel};
+
+impl PlayerChannel for UnixStream {
+    fn read_message_encoded(&mut self) -> Result<(Message, WireEncoding), ServerError> {
+        transmit::read_message_encoded(self)
+    }
+
+    fn write_message_encoded(
+        &mut self,
+        message: &Message,
+        encoding: WireEncoding,
+    ) -> Result<(), ServerError> {
+        transmit::write_message_encoded(self, message, encoding)
+    }
+
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> Result<(), ServerError> {
+        Ok(UnixStream::set_read_timeout(self, timeout)?)
+    }
+
+    fn stop(&mut self) -> Result<(), ServerError> {
+        self.shutdown(Shutdown::Both)
+            .map_err(|err| ServerError::Other(err.to_string().into()))
+    }
+
+    fn clone_instance(&self) -> Self {
+        self.try_clone().unwrap()
+    }
+
+    fn get_name(&self) -> Option<Cow<'_, str>> {
+        self.peer_addr().ok().and_then(|addr| {
+            addr.as_pathname()
+                .map(|path| path.display().to_string().into())
+        })
+    }
+}
+
+impl LobbyListener<UnixStream> for UnixListener {
+    fn accept_client(&mut self) -> Result<(UnixStream, Cow<'_, str>), ServerError> {
+        let (stream, _) = self.accept()?;
+
+        // Clients are usually unnamed, name them after their file descriptor instead.
+        let name = format!("unix:{}", stream.as_raw_fd());
+
+        Ok((stream, name.into()))
+    }
+
+    fn get_binding_name(&self) -> Option<Cow<'_, str>> {
+        self.local_addr().ok().and_then(|addr| {
+            addr.as_pathname()
+                .map(|path| format!("unix:{}", path.display()).into())
+        })
+    }
+}
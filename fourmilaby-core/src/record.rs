@@ -1,69 +1,404 @@
 //! Record replay system.
 use std::{
-    sync::mpsc::{self, Receiver},
+    fmt::Display,
+    sync::mpsc::{self, RecvTimeoutError},
     thread,
+    time::{Duration, Instant},
 };
 
 use crate::{
     error::ServerError,
     game::{
-        record::GameRecord, state::GameState, GameSession, GameSessionMessage,
-        GameSessionMessageKind,
+        record::{GameRecord, RecordEvent},
+        state::{GameState, StateChecksum},
     },
-    message::types::Message,
+    message::types::{Message, ReplayFrameMessageBody, ReplayPlayerBody},
     protocols::PlayerChannel,
 };
 
-/// Create and replay a game using [`GameRecord`], send the infos in [`std::net::TcpStream`].
+/// The delay between each frame sent to the watcher.
+const REPLAY_FRAME_DELAY: Duration = Duration::from_millis(100);
+
+const REPLAY_MIN_SPEED: f32 = 0.5;
+const REPLAY_MAX_SPEED: f32 = 16.0;
+
+/// A recorded game re-simulated through a headless [`GameState`].
+pub struct Replay {
+    record: GameRecord,
+    state: GameState,
+
+    /// Time of each event since the start of the game, follows [`GameRecord::events`].
+    timestamps: Box<[Duration]>,
+
+    /// Index of the next event to apply.
+    next_event: usize,
+
+    /// Current time of the replay.
+    time: Duration,
+}
+
+impl Replay {
+    /// Create a replay of `record`, at the start of the game.
+    pub fn new(record: GameRecord) -> Self {
+        let timestamps = record
+            .events
+            .iter()
+            .scan(Duration::ZERO, |time, event| {
+                *time += event.delay;
+                Some(*time)
+            })
+            .collect();
+
+        Self {
+            state: Self::initial_state(&record),
+            record,
+            timestamps,
+            next_event: 0,
+            time: Duration::ZERO,
+        }
+    }
+
+    fn initial_state(record: &GameRecord) -> GameState {
+        GameState::new(record.maze.clone(), record.metadata.game)
+    }
+
+    /// Duration of the recorded game.
+    pub fn duration(&self) -> Duration {
+        self.timestamps.last().copied().unwrap_or_default()
+    }
+
+    /// Current time of the replay.
+    pub fn time(&self) -> Duration {
+        self.time
+    }
+
+    pub fn state(&self) -> &GameState {
+        &self.state
+    }
+
+    /// Apply a recorded event to the game state, the events that don't change it are ignored.
+    fn apply(&mut self, index: usize) -> Result<(), ServerError> {
+        let record = &self.record.events[index];
+
+        let result = match &record.event {
+            RecordEvent::Join => {
+                self.state.add_player(record.player);
+                Ok(())
+            }
+            RecordEvent::ClientMessage { message } => match self.state.config.tick {
+                Some(_) => self.state.queue_message(&record.player, message),
+                None => self
+                    .state
+                    .process_message(&record.player, message)
+                    .map(|_| ()),
+            },
+            RecordEvent::UpdatePheromon => {
+                self.state.update_pheromon();
+                Ok(())
+            }
+            RecordEvent::Tick => {
+                self.state.step_tick();
+                Ok(())
+            }
+            RecordEvent::Reconnect | RecordEvent::Disconnect | RecordEvent::UpdateAllPlayers => {
+                Ok(())
+            }
+        };
+
+        match result {
+            // Rejected by the game too, without changing its state.
+            Err(ServerError::UnexpectedParameter) => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Apply the next event, returns its index along with the result of applying it,
+    /// or `None` at the end of the replay. The replay goes on even if the event failed.
+    pub fn try_step(&mut self) -> Option<(usize, Result<(), ServerError>)> {
+        let index = self.next_event;
+        let timestamp = *self.timestamps.get(index)?;
+
+        let result = self.apply(index);
+        self.next_event += 1;
+        self.time = timestamp;
+
+        Some((index, result))
+    }
+
+    /// Apply the next event, returns its index or `None` at the end of the replay.
+    /// The events that can't be applied are ignored.
+    pub fn step(&mut self) -> Option<usize> {
+        let (index, result) = self.try_step()?;
+
+        if let Err(err) = result {
+            eprintln!("Replay: event {index} ignored : {err}");
+        }
+
+        Some(index)
+    }
+
+    /// Advance the replay up to `time` (capped to the duration of the game).
+    pub fn advance(&mut self, time: Duration) {
+        let time = time.min(self.duration());
+
+        while self
+            .timestamps
+            .get(self.next_event)
+            .is_some_and(|timestamp| *timestamp <= time)
+        {
+            self.step();
+        }
+
+        self.time = time;
+    }
+
+    /// Seek to `time`, re-simulating the game from the start when seeking backward.
+    pub fn seek(&mut self, time: Duration) {
+        if time < self.time {
+            self.state = Self::initial_state(&self.record);
+            self.next_event = 0;
+            self.time = Duration::ZERO;
+        }
+
+        self.advance(time);
+    }
+
+    /// Build the frame sent to the watchers.
+    pub fn frame(&self, speed: f32, paused: bool) -> ReplayFrameMessageBody {
+        ReplayFrameMessageBody {
+            time: self.time.as_millis() as u64,
+            duration: self.duration().as_millis() as u64,
+            speed,
+            paused,
+            players: self
+                .state
+                .player_order
+                .iter()
+                .filter_map(|uuid| self.state.players.get(uuid))
+                .map(|player| ReplayPlayerBody {
+                    player_column: player.position.0,
+                    player_line: player.position.1,
+                    player_has_food: player.has_food,
+                    player_team: player.team,
+                    player_food_delivered: player.food_delivered,
+                })
+                .collect(),
+            pheromon: self
+                .state
+                .pheromon
+                .first()
+                .map(|layer| layer.levels.clone())
+                .unwrap_or_default(),
+            total_food_delivered: self.state.food_delivered,
+        }
+    }
+}
+
+/// The first point where a replay differs from the recorded game.
+#[derive(Debug, Clone)]
+pub struct Divergence {
+    /// Index of the event in [`GameRecord::events`].
+    pub event: usize,
+    /// Time of the event since the start of the game.
+    pub time: Duration,
+    pub kind: DivergenceKind,
+}
+
+/// How a replay differs from the recorded game.
+#[derive(Debug, Clone)]
+pub enum DivergenceKind {
+    /// The state doesn't match the checkpoint of the event.
+    Checksum {
+        expected: StateChecksum,
+        found: StateChecksum,
+    },
+    /// The event can't be applied to the state (e.g. its player never joined).
+    InvalidEvent(ServerError),
+}
+
+impl Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "event {} ({} ms) diverges :",
+            self.event,
+            self.time.as_millis()
+        )?;
+
+        match &self.kind {
+            DivergenceKind::Checksum { expected, found } => {
+                if expected.players != found.players {
+                    write!(
+                        f,
+                        " players ({:016x} != {:016x})",
+                        expected.players, found.players
+                    )?;
+                }
+
+                if expected.pheromon != found.pheromon {
+                    write!(
+                        f,
+                        " pheromon ({:016x} != {:016x})",
+                        expected.pheromon, found.pheromon
+                    )?;
+                }
+
+                Ok(())
+            }
+            DivergenceKind::InvalidEvent(err) => write!(f, " {err}"),
+        }
+    }
+}
+
+/// Replay the whole [`GameRecord`] through a headless [`GameState`] and check the state
+/// against each checkpoint, returns the amount of checkpoints verified or the first divergence.
+pub fn verify_record(game_record: GameRecord) -> Result<usize, Divergence> {
+    let mut replay = Replay::new(game_record);
+    let mut checkpoints = 0;
+
+    while let Some((index, result)) = replay.try_step() {
+        if let Err(err) = result {
+            return Err(Divergence {
+                event: index,
+                time: replay.time,
+                kind: DivergenceKind::InvalidEvent(err),
+            });
+        }
+
+        if let Some(expected) = replay.record.events[index].checkpoint {
+            let found = replay.state.checksum();
+
+            if found != expected {
+                return Err(Divergence {
+                    event: index,
+                    time: replay.time,
+                    kind: DivergenceKind::Checksum { expected, found },
+                });
+            }
+
+            checkpoints += 1;
+        }
+    }
+
+    Ok(checkpoints)
+}
+
+/// Stream the replay of a [`GameRecord`] to the [`PlayerChannel`] at `speed`,
+/// until the watcher disconnects. The watcher controls the playback using
+/// [`Message::ReplayControl`] messages.
 pub fn replay_game<C: PlayerChannel>(
-    channel: C,
+    mut channel: C,
     game_record: GameRecord,
+    speed: f32,
 ) -> Result<(), ServerError> {
-    // Create a new game, and take its
-    let info = GameSession::start_new(GameState::new(game_record.maze), false)?;
-    let game_channel = info.channel.lock()?.clone();
+    let mut replay = Replay::new(game_record);
+    let mut speed = speed.clamp(REPLAY_MIN_SPEED, REPLAY_MAX_SPEED);
+    let mut paused = false;
 
-    let (send_channel, recv_channel) = mpsc::channel::<Message>();
+    // Make a thread that will receive the controls from the watcher.
+    let (control_sender, control_receiver) = mpsc::channel();
+    let reader = channel.clone_instance();
 
-    // Initialize all the players of the record.
-    game_record.players.iter().for_each(|uuid| {
-        game_channel
-            .send(GameSessionMessage(
-                *uuid,
-                GameSessionMessageKind::InitializePlayer(send_channel.clone()),
-            ))
-            .unwrap()
-    });
+    thread::Builder::new()
+        .name(format!(
+            "replay control {}",
+            channel.get_name().unwrap_or_default()
+        ))
+        .spawn(move || replay_control_thread(reader, control_sender))?;
 
-    // Make a thread that will receive messages from game session, and forward the to the stream.
-    let forward_thread =
-        thread::spawn(move || replay_game_forward_thread(channel, recv_channel).unwrap());
+    let mut last_frame = Instant::now();
 
-    // Reuse this thread to send events to server considering delays.
-    for record in game_record.messages.iter() {
-        thread::sleep(record.delay);
+    loop {
+        channel.write_message(&Message::ReplayFrame(replay.frame(speed, paused)))?;
 
-        game_channel
-            .send(GameSessionMessage(
-                record.player,
-                GameSessionMessageKind::ClientMessage(record.message.clone()),
-            ))
-            .unwrap()
-    }
+        match control_receiver.recv_timeout(REPLAY_FRAME_DELAY) {
+            Ok(Message::ReplayControl(control)) => {
+                if let Some(new_speed) = control.speed {
+                    speed = new_speed.clamp(REPLAY_MIN_SPEED, REPLAY_MAX_SPEED);
+                }
+
+                if let Some(new_paused) = control.paused {
+                    paused = new_paused;
+                }
+
+                if let Some(seek) = control.seek {
+                    replay.seek(Duration::from_millis(seek));
+                }
+            }
+            Ok(unexpected) => channel.write_message(&Message::Unexpected {
+                expected: vec!["replayControl".into()],
+                received: unexpected.into(),
+            })?,
+            Err(RecvTimeoutError::Timeout) => (),
 
-    forward_thread.join().unwrap();
+            // The watcher is gone.
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
 
-    Ok(())
+        let elapsed = last_frame.elapsed();
+        last_frame = Instant::now();
+
+        if !paused {
+            replay.advance(replay.time() + elapsed.mul_f32(speed));
+        }
+    }
+
+    channel.stop()
 }
 
-/// Pipe the messages from the [`Receiver<Message>`] into the [`std::net::TcpStream`].
-pub fn replay_game_forward_thread<C: PlayerChannel>(
+/// Forward the messages read from the [`PlayerChannel`] to the replay loop.
+fn replay_control_thread<C: PlayerChannel>(
     mut channel: C,
-    receiver: Receiver<Message>,
+    sender: mpsc::Sender<Message>,
 ) -> Result<(), ServerError> {
-    receiver
-        .into_iter()
-        .for_each(|message| channel.write_message(&message).unwrap());
+    loop {
+        sender.send(channel.read_message()?)?;
+    }
+}
 
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{
+        game::record::{EventRecord, RecordMetadata},
+        maze::Maze,
+        message::types::{MoveDirection, MoveMessageBody},
+    };
+
+    #[test]
+    fn unknown_player_diverges() {
+        let record = GameRecord {
+            events: Box::new([EventRecord {
+                delay: Duration::ZERO,
+                player: Uuid::nil(),
+                event: RecordEvent::ClientMessage {
+                    message: Message::Move(MoveMessageBody {
+                        direction: MoveDirection::North,
+                    }),
+                },
+                checkpoint: None,
+            }]),
+            maze: Maze::default(),
+            players: Box::new([]),
+            scores: Box::new([]),
+            metadata: RecordMetadata {
+                session: Uuid::nil(),
+                start_time: 0,
+                generator: Default::default(),
+                game: Default::default(),
+                seed: None,
+            },
+        };
+
+        assert!(matches!(
+            verify_record(record),
+            Err(Divergence {
+                event: 0,
+                kind: DivergenceKind::InvalidEvent(ServerError::ExpiredUuid),
+                ..
+            })
+        ));
+    }
 }
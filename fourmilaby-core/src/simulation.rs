@@ -0,0 +1,167 @@
+//! Headless fast-forward simulations.
+//!
+//! The [`AntAI`] are attached directly to a [`GameState`] running in tick-based mode,
+//! without any game session, channel or sleep : a game runs as fast as the CPU allows.
+use std::time::Duration;
+
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    ai::{AntAI, Ants},
+    config::{SimulationConfig, TickConfig},
+    error::ServerError,
+    game::state::GameState,
+    maze::generator::generate_maze,
+    message::types::{GameOverReason, JoinMessageBody, Message},
+};
+
+/// Pheromon level above which a tile is considered covered, evaporation never reaches `0`.
+const PHEROMON_COVERAGE_THRESHOLD: f32 = 0.01;
+
+/// Part of the maze covered by a pheromon layer at the end of a game.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PheromonCoverage {
+    pub name: Box<str>,
+    pub coverage: f32,
+}
+
+/// Statistics of a simulated game.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SimulationStats {
+    pub seed: u64,
+    pub steps: u64,
+    pub food_delivered: u32,
+    pub team_food_delivered: Box<[u32]>,
+    pub pheromon_coverage: Vec<PheromonCoverage>,
+
+    /// Why the game ended, `None` if it reached the maximum amount of steps.
+    pub game_over: Option<GameOverReason>,
+}
+
+/// A single headless game.
+pub struct Simulation<AI: AntAI> {
+    state: GameState,
+    ants: Ants<AI>,
+    seed: u64,
+}
+
+impl<AI: AntAI> Simulation<AI> {
+    /// Create a new game from a maze generated using `seed`.
+    pub fn new(config: &SimulationConfig, seed: u64) -> Result<Self, ServerError> {
+        let rng = fastrand::Rng::with_seed(seed);
+
+        let maze = generate_maze(
+            &config.generator,
+            &JoinMessageBody {
+                difficulty: config.difficulty,
+                player_id: None,
+                room: None,
+                pheromon_encoding: Default::default(),
+            },
+            &rng,
+        )?;
+
+        // The simulation is always tick-based.
+        let mut game = config.game;
+        game.tick.get_or_insert_with(TickConfig::default);
+
+        let mut state = GameState::new(maze.clone(), game);
+
+        // Ants get predictable UUIDs, their moves are resolved in this order.
+        let uuids: Vec<Uuid> = (0..config.ants as u128).map(Uuid::from_u128).collect();
+
+        for uuid in uuids.iter() {
+            state.add_player(*uuid);
+        }
+
+        Ok(Self {
+            state,
+            // The ants use the random generator after the maze generation.
+            ants: Ants::new(&uuids, maze, rng),
+            seed,
+        })
+    }
+
+    /// Run a tick : each ant chooses its move, then the moves are resolved.
+    pub fn step(&mut self) {
+        let state = &self.state;
+        let moves = self
+            .ants
+            .step(|uuid| Some(state.info_message(state.players.get(uuid)?)));
+
+        for (uuid, movement) in moves {
+            // Can't fail, the message is a move.
+            let _ = self.state.queue_message(&uuid, &Message::Move(movement));
+        }
+
+        self.state.step_tick();
+
+        // Let the ants know about the depleted food sources.
+        for (column, line) in self.state.take_depleted_food() {
+            self.ants.food_depleted(column, line);
+        }
+    }
+
+    /// Time elapsed in the game, counted in ticks.
+    fn elapsed(&self) -> Duration {
+        let tick_duration = self.state.config.tick.unwrap_or_default().tick_duration;
+
+        Duration::from_millis(self.state.tick * tick_duration)
+    }
+
+    /// Run the game until an end condition is met or `max_steps` steps are done.
+    pub fn run(mut self, max_steps: u64) -> SimulationStats {
+        let mut game_over = None;
+
+        while game_over.is_none() && self.state.tick < max_steps {
+            self.step();
+
+            game_over = self.state.check_end_conditions(self.elapsed());
+        }
+
+        self.stats(game_over)
+    }
+
+    /// Get the statistics of the game.
+    pub fn stats(&self, game_over: Option<GameOverReason>) -> SimulationStats {
+        SimulationStats {
+            seed: self.seed,
+            steps: self.state.tick,
+            food_delivered: self.state.food_delivered,
+            team_food_delivered: self.state.team_food_delivered.clone(),
+            pheromon_coverage: self
+                .state
+                .pheromon
+                .iter()
+                .map(|layer| PheromonCoverage {
+                    name: layer.name.clone(),
+                    coverage: layer
+                        .levels
+                        .iter()
+                        .filter(|level| **level > PHEROMON_COVERAGE_THRESHOLD)
+                        .count() as f32
+                        / layer.levels.len().max(1) as f32,
+                })
+                .collect(),
+            game_over,
+        }
+    }
+}
+
+/// Run all the games of `config` in parallel, the stats are in the same order as the seeds.
+pub fn run_simulations<AI: AntAI>(
+    config: &SimulationConfig,
+) -> Vec<Result<SimulationStats, ServerError>> {
+    (0..config.games as u64)
+        .into_par_iter()
+        .map(|index| {
+            let seed = config.seed.wrapping_add(index);
+
+            Ok(Simulation::<AI>::new(config, seed)?.run(config.max_steps))
+        })
+        .collect()
+}
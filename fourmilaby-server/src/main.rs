@@ -1,10 +1,78 @@
-use std::net::{SocketAddr, TcpListener};
+//! The FourmiLaby server.
+//!
+//! Usage:
+//! - `fourmilaby-server` : run the server, configured by `config.json`.
+//! - `fourmilaby-server schema [directory]` : write the JSON Schema and the TypeScript
+//!   definitions of the protocol in `directory` (`schema` by default).
+use std::{fs, net::TcpListener, path::Path};
 
-use fourmilaby_core::{config, error::ServerError, lobby};
+use fourmilaby_core::{
+    config::{self, BindConfig},
+    error::ServerError,
+    lobby,
+    message::schema,
+    protocols::tungstenite::WebSocketListener,
+};
 
 fn main() -> Result<(), ServerError> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    match args.first().map(String::as_str) {
+        Some("schema") => export_schema(Path::new(args.get(1).map_or("schema", String::as_str))),
+        _ => serve(),
+    }
+}
+
+/// Write the protocol definitions, see [`schema`].
+fn export_schema(directory: &Path) -> Result<(), ServerError> {
+    let protocol = schema::protocol_schema();
+
+    fs::create_dir_all(directory)?;
+    fs::write(
+        directory.join("protocol.schema.json"),
+        serde_json::to_string_pretty(&protocol)? + "\n",
+    )?;
+    fs::write(
+        directory.join("protocol.d.ts"),
+        schema::typescript(&protocol),
+    )?;
+
+    println!("Protocol definitions written to {}", directory.display());
+
+    Ok(())
+}
+
+/// Run the lobby on all the bindings of the configuration.
+fn serve() -> Result<(), ServerError> {
     let config = config::load_config(None).expect("Unable to load config file.");
 
-    let lobby = lobby::Lobby::new(config.lobby);
-    lobby.run(TcpListener::bind(SocketAddr::new(config.ip, config.port))?)
+    let mut lobby = lobby::Lobby::new(config.lobby);
+
+    for bind in config.binds {
+        match bind {
+            BindConfig::Tcp(addr) => lobby.add_listener(TcpListener::bind(addr)?),
+            BindConfig::WebSocket(addr) => {
+                lobby.add_listener(WebSocketListener(TcpListener::bind(addr)?))
+            }
+            #[cfg(unix)]
+            BindConfig::Unix(path) => {
+                use std::os::unix::{fs::FileTypeExt, net::UnixListener};
+
+                // Remove the socket left by a previous instance (if any).
+                if std::fs::metadata(&path).is_ok_and(|meta| meta.file_type().is_socket()) {
+                    std::fs::remove_file(&path)?;
+                }
+
+                lobby.add_listener(UnixListener::bind(path)?)
+            }
+            #[cfg(not(unix))]
+            BindConfig::Unix(_) => {
+                return Err(ServerError::Other(
+                    "Unix sockets are not supported on this platform".into(),
+                ))
+            }
+        }
+    }
+
+    lobby.run()
 }
@@ -0,0 +1,73 @@
+//! Headless simulation runner, writes the statistics of each game as JSON lines on stdout.
+//!
+//! Usage:
+//! - `fourmilaby-sim [simulation config path]` : run the simulations.
+//! - `fourmilaby-sim verify <record path>...` : replay the records and check their checkpoints.
+use std::{
+    io::{self, Write},
+    path::Path,
+};
+
+use fourmilaby_core::{
+    ai::probabilistic::ProbabilisticAnt, config, error::ServerError, game::record::GameRecord,
+    record, simulation,
+};
+
+fn main() -> Result<(), ServerError> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    match args.first().map(String::as_str) {
+        Some("verify") => verify(&args[1..]),
+        config_path => simulate(config_path),
+    }
+}
+
+/// Verify the records, fails if any of them diverges.
+fn verify(paths: &[String]) -> Result<(), ServerError> {
+    let mut diverging = 0;
+
+    for path in paths {
+        match record::verify_record(GameRecord::load(Path::new(path))?) {
+            Ok(checkpoints) => println!("{path}: ok ({checkpoints} checkpoints)"),
+            Err(divergence) => {
+                println!("{path}: {divergence}");
+                diverging += 1;
+            }
+        }
+    }
+
+    match diverging {
+        0 => Ok(()),
+        _ => Err(ServerError::Other(
+            format!("{diverging} diverging record(s)").into(),
+        )),
+    }
+}
+
+/// Run the simulations of the configuration.
+fn simulate(config_path: Option<&str>) -> Result<(), ServerError> {
+    let config = config::load_simulation_config(config_path)?;
+
+    let results = simulation::run_simulations::<ProbabilisticAnt>(&config);
+
+    let mut stdout = io::stdout().lock();
+    let mut food_delivered = 0u64;
+
+    for result in results.iter() {
+        match result {
+            Ok(stats) => {
+                food_delivered += stats.food_delivered as u64;
+
+                writeln!(stdout, "{}", serde_json::to_string(stats)?)?;
+            }
+            Err(err) => eprintln!("Simulation failed : {err}"),
+        }
+    }
+
+    eprintln!(
+        "{} games simulated, {food_delivered} food delivered",
+        results.len()
+    );
+
+    Ok(())
+}
@@ -0,0 +1,440 @@
+// Generated from the protocol JSON Schema, do not edit.
+
+/**
+ * Another ant seen by the player.
+ */
+export interface AntBody {
+  column: number;
+  hasFood: boolean;
+  /**
+   * Public identifier of the ant, the player UUIDs are never disclosed.
+   */
+  id: number;
+  line: number;
+  team: number;
+}
+
+/**
+ * Optional feature of the protocol.
+ */
+export type Capability = "messagePack" | "pheromonDeltas" | "fogOfWar" | "rooms" | "replays" | "heartbeat" | "unknown";
+
+/**
+ * Details of an [`ErrorCode::MissingCapability`] error.
+ */
+export interface CapabilityDetails {
+  capability: Capability;
+}
+
+/**
+ * Message received by the server by the client in the lobby to create a private room.
+ */
+export interface CreateRoomMessageBody {
+  /**
+   * Asked difficulty.
+   */
+  difficulty: number;
+}
+
+/**
+ * A [`ServerError`] as sent to the clients.
+ */
+export interface ErrorBody {
+  code: ErrorCode;
+  /**
+   * Data specific to the error code, see [`VersionDetails`], [`RecordDetails`] and [`CapabilityDetails`].
+   */
+  details?: unknown;
+  /**
+   * Human readable description of the error, not meant to be parsed.
+   */
+  message: string;
+}
+
+/**
+ * Stable identifier of a [`ServerError`], for the clients to branch on.
+ *
+ * Codes are never renamed nor reused, new ones may be added by newer servers.
+ */
+export type ErrorCode = "invalidMaze" | "transmission" | "serialization" | "alreadyConnected" | "unexpectedParameter" | "incompatibleVersion" | "timedOut" | "badMessage" | "expiredUuid" | "unknownRoom" | "gameFull" | "unknownRecord" | "missingCapability" | "internal" | "unknown";
+
+/**
+ * Message sent by the server to the clients when a food source is depleted, the tile must not be considered as food anymore.
+ */
+export interface FoodDepletedMessageBody {
+  column: number;
+  line: number;
+}
+
+/**
+ * Message sent by the server to the client when the game ends, the game session is closed right after.
+ */
+export interface GameOverMessageBody {
+  /**
+   * Rank of the player receiving this message.
+   */
+  playerRank: number;
+  playerTeam: number;
+  reason: GameOverReason;
+  /**
+   * Standings of all the players, from the best to the worst.
+   */
+  standings: PlayerStanding[];
+  /**
+   * Standings of all the teams, from the best to the worst.
+   */
+  teamStandings: TeamStanding[];
+  totalFoodDelivered: number;
+}
+
+/**
+ * Why a game ended.
+ */
+export type GameOverReason = "timeLimit" | "foodGoal" | "foodDepleted";
+
+/**
+ * Keep-alive message, a [`Message::Ping`] is answered by a [`Message::Pong`] with the same `id`.
+ *
+ * The server pings the clients that negotiated [`Capability::Heartbeat`] during the game, and disconnects the ones that stay silent for too long.
+ */
+export interface HeartbeatMessageBody {
+  id: number;
+}
+
+/**
+ * Message exchanged when connecting, before any other message : the client sends its protocol version and capabilities, the server answers with its own version and the capabilities both support, or with an error if the client is incompatible.
+ */
+export interface HelloMessageBody {
+  capabilities?: Capability[];
+  version: number;
+}
+
+/**
+ * Message sent by the server to the client that contains the current game view of the player.
+ */
+export interface InfoMessageBody {
+  /**
+   * Other ants visible by the player, depends on [`crate::config::GameConfig::ant_visibility`].
+   */
+  ants?: AntBody[];
+  pheromon: number[];
+  /**
+   * Changes of the pheromon grids received previously, when `pheromon` is empty.
+   */
+  pheromonDeltas?: PheromonDeltaBody[];
+  /**
+   * Additional pheromon layers (`pheromon` being the `food` layer).
+   */
+  pheromonLayers?: PheromonLayerBody[];
+  playerColumn: number;
+  /**
+   * Amount of food the player brought back to the nest.
+   */
+  playerFoodDelivered?: number;
+  playerHasFood: boolean;
+  playerLine: number;
+  /**
+   * Team of the player, the nest of the team is [`Maze::nests`]`[player_team]`.
+   */
+  playerTeam?: number;
+  /**
+   * Amount of food brought back to the nest by the team of the player.
+   */
+  teamFoodDelivered?: number;
+  /**
+   * With the fog of war, the tiles currently perceived by the player (all the tiles it revealed in the first message after joining), the pheromon of the other tiles is `0`.
+   */
+  tiles?: TileBody[];
+  /**
+   * Amount of food brought back to the nest by all the players of the game.
+   */
+  totalFoodDelivered?: number;
+}
+
+/**
+ * Message received by the server by the client in the lobby to initiate the matchmaking.
+ */
+export interface JoinMessageBody {
+  /**
+   * Asked difficulty.
+   */
+  difficulty: number;
+  /**
+   * How the client wants to receive the pheromon, see [`OkMazeMessageBody::pheromon_encoding`].
+   */
+  pheromonEncoding?: PheromonEncoding;
+  /**
+   * Optional player UUID for reconnecting session.
+   */
+  playerId?: string | null;
+  /**
+   * Optional room code, to join a private room (see [`Message::CreateRoom`]).
+   */
+  room?: string | null;
+}
+
+/**
+ * A maze or "Labyrinth".
+ */
+export interface Maze {
+  nbColumn: number;
+  nbLine: number;
+  /**
+   * Position of the first nest (team 0), see `nests`.
+   */
+  nestColumn: number;
+  nestLine: number;
+  /**
+   * All the nests of the maze, each nest is owned by the team of the same index.
+   */
+  nests?: Nest[];
+  /**
+   * Array of tiles, where each [`u8`] is an actual tile (that can be wrapped into a [`Tile`]). Is `nb_column` * `nb_line` large.
+   *
+   * Follows this ordering : ```text +-+-+-+ x -> |6|7|8| +-+-+-+ |3|4|5| +-+-+-+ |0|1|2|  ^ +-+-+-+  | y
+   *
+   * index = x + y * nb_column ```
+   */
+  tiles: number[];
+}
+
+/**
+ * Enumeration of all the possible messages formats.
+ */
+export type Message = {
+  body: HelloMessageBody;
+  type: "hello";
+} | {
+  body: JoinMessageBody;
+  type: "join";
+} | {
+  body: OkMazeMessageBody;
+  type: "okMaze";
+} | {
+  body: InfoMessageBody;
+  type: "info";
+} | {
+  body: ErrorBody;
+  type: "error";
+} | {
+  body: MoveMessageBody;
+  type: "move";
+} | {
+  body: CreateRoomMessageBody;
+  type: "createRoom";
+} | {
+  body: RoomCreatedMessageBody;
+  type: "roomCreated";
+} | {
+  body: FoodDepletedMessageBody;
+  type: "foodDepleted";
+} | {
+  body: GameOverMessageBody;
+  type: "gameOver";
+} | {
+  body: WatchReplayMessageBody;
+  type: "watchReplay";
+} | {
+  body: ReplayControlMessageBody;
+  type: "replayControl";
+} | {
+  body: ReplayFrameMessageBody;
+  type: "replayFrame";
+} | {
+  body: HeartbeatMessageBody;
+  type: "ping";
+} | {
+  body: HeartbeatMessageBody;
+  type: "pong";
+} | {
+  body: {
+    expected: string[];
+    received: Message;
+  };
+  type: "unexpected";
+};
+
+/**
+ * Each movement a player can do : 0 North, 1 South, 2 East, 3 West.
+ */
+export type MoveDirection = 0 | 1 | 2 | 3;
+
+export interface MoveMessageBody {
+  direction: MoveDirection;
+}
+
+/**
+ * The position of a nest in a [`Maze`].
+ */
+export interface Nest {
+  column: number;
+  line: number;
+}
+
+/**
+ * Message sent by the server to the client in the lobby to prepare the client to join the game session.
+ */
+export interface OkMazeMessageBody {
+  maze: Maze;
+  /**
+   * Pheromon encoding accepted by the server (`full` for the servers without delta encoding).
+   */
+  pheromonEncoding?: PheromonEncoding;
+  playerId: string;
+}
+
+/**
+ * Changed levels of a pheromon grid since the previous info message.
+ */
+export interface PheromonDeltaBody {
+  /**
+   * Indices of the changed levels, see [`Maze::tiles`].
+   */
+  indices: number[];
+  /**
+   * Changed grid : `0` for [`InfoMessageBody::pheromon`], `n` for `pheromon_layers[n - 1]`.
+   */
+  layer: number;
+  /**
+   * New levels, follows `indices` ([`PheromonEncoding::Delta`]).
+   */
+  levels?: number[];
+  /**
+   * New quantized levels, follows `indices` ([`PheromonEncoding::QuantizedDelta`]).
+   */
+  quantizedLevels?: number[];
+}
+
+/**
+ * How the pheromon grids are sent in the info messages.
+ */
+export type PheromonEncoding = "full" | "delta" | "quantizedDelta";
+
+/**
+ * A named pheromon layer, the grid follows the [`Maze::tiles`] ordering.
+ */
+export interface PheromonLayerBody {
+  name: string;
+  pheromon: number[];
+}
+
+/**
+ * The final score of a player.
+ */
+export interface PlayerStanding {
+  foodDelivered: number;
+  /**
+   * Rank of the player, starting at 1 (players with the same score share the same rank).
+   */
+  rank: number;
+  team: number;
+}
+
+/**
+ * Details of an [`ErrorCode::UnknownRecord`] error.
+ */
+export interface RecordDetails {
+  id: string;
+}
+
+/**
+ * Message sent by a replay watcher to control the playback, absent fields are left unchanged.
+ */
+export interface ReplayControlMessageBody {
+  paused?: boolean | null;
+  /**
+   * Time to seek to (in milliseconds since the start of the game).
+   */
+  seek?: number | null;
+  /**
+   * Playback speed (from 0.5 to 16).
+   */
+  speed?: number | null;
+}
+
+/**
+ * Message sent by the server to a replay watcher, the state of the replayed game at `time`.
+ */
+export interface ReplayFrameMessageBody {
+  /**
+   * Duration of the replay (in milliseconds).
+   */
+  duration: number;
+  paused: boolean;
+  pheromon: number[];
+  players: ReplayPlayerBody[];
+  speed: number;
+  /**
+   * Current time of the replay (in milliseconds since the start of the game).
+   */
+  time: number;
+  totalFoodDelivered: number;
+}
+
+/**
+ * A player in a [`ReplayFrameMessageBody`].
+ */
+export interface ReplayPlayerBody {
+  playerColumn: number;
+  playerFoodDelivered: number;
+  playerHasFood: boolean;
+  playerLine: number;
+  playerTeam: number;
+}
+
+/**
+ * Message sent by the server to the client in the lobby once the private room is created.
+ */
+export interface RoomCreatedMessageBody {
+  /**
+   * Code to give to [`JoinMessageBody::room`] to join the room.
+   */
+  code: string;
+}
+
+/**
+ * The final score of a team.
+ */
+export interface TeamStanding {
+  foodDelivered: number;
+  /**
+   * Rank of the team, starting at 1 (teams with the same score share the same rank).
+   */
+  rank: number;
+  team: number;
+}
+
+/**
+ * A tile perceived by the player (fog of war), to put in its [`Maze`].
+ */
+export interface TileBody {
+  column: number;
+  line: number;
+  /**
+   * The tile, see [`Maze::tiles`].
+   */
+  tile: number;
+}
+
+/**
+ * Details of an [`ErrorCode::IncompatibleVersion`] error.
+ */
+export interface VersionDetails {
+  maxVersion: number;
+  minVersion: number;
+  version: number;
+}
+
+/**
+ * Message sent by the client to watch the replay of a recorded game.
+ */
+export interface WatchReplayMessageBody {
+  /**
+   * UUID of the recorded game session.
+   */
+  id: string;
+  /**
+   * Initial playback speed (from 0.5 to 16), 1 if not specified.
+   */
+  speed?: number | null;
+}